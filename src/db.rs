@@ -1,51 +1,442 @@
-use crate::OptCheck;
+use crate::dependency;
+use crate::graph::Graph;
+use crate::lint::{self, LintLog, LintResult, LintTool};
+use crate::migration::SCHEMA_VERSION;
+use crate::output;
+use crate::storage;
+use crate::toolchain::{self, Release};
+use crate::usage::{self, ResourceUsage};
+use crate::{OptBisect, OptBisectCommit, OptCheck, OptCompare, OptFmt, OptLog, OptPerf};
 use anstyle::{AnsiColor, Style};
 use anyhow::{anyhow, Result};
 use chrono::serde::ts_seconds;
 use chrono::{DateTime, TimeZone, Utc};
+use clap::ValueEnum;
 use octocrab::models::Code;
-use octocrab::Page;
+use octocrab::{Octocrab, Page};
 use plotters::prelude::*;
 use secrecy::SecretString;
 use semver::Version;
-use serde::{Deserialize, Serialize};
-use std::collections::{HashMap, HashSet};
-use std::fs::{self, File};
-use std::io::Cursor;
-use std::io::{Read, Write};
+use serde::de::{self, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::fs;
+use std::os::unix::process::{CommandExt, ExitStatusExt};
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{ExitStatus, Output, Stdio};
+use std::sync::{Arc, Mutex as StdMutex};
 use std::time::Duration;
+use tokio::process::Command;
+use tokio::sync::{Mutex, OwnedMutexGuard, Semaphore};
 use tokio::time;
 use url::Url;
 use walkdir::WalkDir;
 
-const VERYL_BINARY: &str =
-    "https://github.com/veryl-lang/veryl/releases/latest/download/veryl-x86_64-linux.zip";
-const VERYL_RELEASE_API: &str = "https://api.github.com/repos/veryl-lang/veryl/releases";
+pub const VERYL_RELEASE_API: &str = "https://api.github.com/repos/veryl-lang/veryl/releases";
 const VERYLUP_RELEASE_API: &str = "https://api.github.com/repos/veryl-lang/verylup/releases";
+const SEARCH_PER_PAGE: u8 = 100;
+const SEARCH_MAX_RESULTS: u64 = 1000;
+const SEARCH_MAX_FILE_SIZE: u64 = 384 * 1024;
+const SEARCH_INTERVAL: Duration = Duration::from_secs(7);
+const DEFAULT_BUILD_TIMEOUT: Duration = Duration::from_secs(600);
+const LOG_EXCERPT_SIZE: usize = 4096;
+const CACHE_DIR: &str = "cache";
+const WORK_DIR: &str = "work";
+const VERYL_CACHE_DIR: &str = "veryl-cache";
+const COMPILER_DIR: &str = "compilers";
+const OUTPUT_DIR: &str = "outputs";
+const FETCH_RETRY: u32 = 4;
+const FETCH_RETRY_INTERVAL: Duration = Duration::from_secs(5);
+/// Timeout of git commands which access remote repositories
+const GIT_REMOTE_TIMEOUT: Duration = Duration::from_secs(600);
+/// stderr patterns of git which are likely to be resolved by retry
+const NETWORK_ERRORS: &[&str] = &[
+    "Could not resolve host",
+    "Failed to connect",
+    "Connection timed out",
+    "Connection refused",
+    "Connection reset",
+    "Operation timed out",
+    "early EOF",
+    "RPC failed",
+    "unexpected disconnect",
+    "remote end hung up",
+    "The requested URL returned error: 5",
+    // Reported by git_remote
+    "git timed out",
+];
+const DEPENDENCY_ERRORS: &[&str] = &[
+    "git operation failure",
+    "git command failure",
+    "failed to clone",
+    "failed to fetch",
+];
 
 #[derive(Default, Serialize, Deserialize, Debug)]
 pub struct Db {
+    pub schema_version: u64,
     pub discovered: Vec<Discovered>,
+    #[serde(deserialize_with = "deserialize_projects")]
     pub projects: HashMap<u64, Project>,
-    #[serde(default)]
+    /// ID allocated to the next inserted project.
+    /// IDs are never reused even if projects are removed.
+    pub next_project_id: u64,
     pub veryl_downloads: HashMap<Version, Vec<Download>>,
-    #[serde(default)]
     pub verylup_downloads: HashMap<Version, Vec<Download>>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
 pub struct Project {
     pub url: Url,
+    /// Relative path of the directory containing Veryl.toml from the repository root
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub path: String,
     pub build_logs: Vec<BuildLog>,
+    /// Git dependencies declared in Veryl.toml at the latest build
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub dependencies: Vec<Url>,
+    /// Build logs by bisect which are not a part of the history of the default branch
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub bisect_logs: Vec<BuildLog>,
 }
 
-#[derive(Serialize, Deserialize, Debug)]
+impl Project {
+    fn name(&self) -> String {
+        let url = self.url.as_str().trim_end_matches('/');
+        if self.path.is_empty() {
+            url.to_string()
+        } else {
+            format!("{url}/{}", self.path)
+        }
+    }
+}
+
+impl fmt::Display for Project {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.path.is_empty() {
+            self.url.fmt(f)
+        } else {
+            write!(f, "{} [{}]", self.url, self.path)
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct BuildLog {
     pub rev: String,
     pub veryl_version: Version,
-    pub result: bool,
+    pub result: BuildResult,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub stdout: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub stderr: String,
+    /// Resources used by `veryl build`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<ResourceUsage>,
+    /// Results of each step if steps other than `veryl build` are configured
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub steps: Vec<StepLog>,
+    /// Result of the SystemVerilog tool which checked the generated code
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lint: Option<LintLog>,
+}
+
+impl BuildLog {
+    pub fn print(&self, prj: &Project) {
+        println!("Project : {prj}");
+        println!("Revision: {}", self.rev);
+        println!("Veryl   : {}", self.veryl_version);
+        if let Some(details) = self.result.details() {
+            println!("Result  : {} ({details})", self.result);
+        } else {
+            println!("Result  : {}", self.result);
+        }
+        if let Some(x) = &self.usage {
+            println!(
+                "Usage   : {:.2}s wall, {:.2}s user, {:.2}s sys, {} KiB max RSS",
+                x.wall_time,
+                x.user_time,
+                x.sys_time,
+                x.max_rss / 1024
+            );
+        }
+        for step in &self.steps {
+            println!("Step    : {} ({})", step.result, step.step);
+        }
+        if let Some(lint) = &self.lint {
+            println!("Lint    : {} ({})", lint.result, lint.tool);
+        }
+        if !self.stdout.is_empty() {
+            println!("--- stdout ---");
+            println!("{}", self.stdout.trim_end());
+        }
+        if !self.stderr.is_empty() {
+            println!("--- stderr ---");
+            println!("{}", self.stderr.trim_end());
+        }
+        if let Some(lint) = self.lint.as_ref().filter(|x| !x.stderr.is_empty()) {
+            println!("--- {} ---", lint.tool);
+            println!("{}", lint.stderr.trim_end());
+        }
+    }
+
+    /// Both the build and the check of generated code succeeded
+    pub fn is_clean(&self) -> bool {
+        self.result.is_success() && self.lint.as_ref().is_none_or(|x| x.result.is_success())
+    }
+}
+
+/// Subcommand of veryl run for each project
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum Step {
+    Check,
+    Build,
+    Fmt,
+    Test,
+    Doc,
+}
+
+impl Step {
+    fn args(&self) -> &'static [&'static str] {
+        match self {
+            Step::Check => &["check"],
+            Step::Build => &["build"],
+            Step::Fmt => &["fmt", "--check"],
+            Step::Test => &["test"],
+            Step::Doc => &["doc"],
+        }
+    }
+}
+
+impl fmt::Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.args().join(" ").fmt(f)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct StepLog {
+    pub step: Step,
+    pub result: BuildResult,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub stdout: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub stderr: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<ResourceUsage>,
+}
+
+struct VerylRun {
+    result: BuildResult,
+    stdout: String,
+    stderr: String,
+    usage: Option<ResourceUsage>,
+}
+
+/// Keep the tail of output because compiler errors are usually reported at the end
+fn excerpt(buf: &[u8]) -> String {
+    let text = String::from_utf8_lossy(buf);
+    let mut start = text.len().saturating_sub(LOG_EXCERPT_SIZE);
+    while !text.is_char_boundary(start) {
+        start += 1;
+    }
+    text[start..].to_string()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum BuildResult {
+    Success,
+    CompileError {
+        exit_code: Option<i32>,
+    },
+    CompilerPanic {
+        message: String,
+    },
+    CloneFailed {
+        exit_code: Option<i32>,
+    },
+    NoVerylToml,
+    Timeout {
+        seconds: u64,
+    },
+    OutOfMemory {
+        limit: Option<u64>,
+    },
+    DependencyFetchFailed {
+        message: String,
+    },
+    /// Network errors continued after all retries, so the project itself may be fine
+    InfrastructureFailure {
+        message: String,
+    },
+}
+
+impl BuildResult {
+    pub fn is_success(&self) -> bool {
+        *self == BuildResult::Success
+    }
+
+    pub fn is_infrastructure_failure(&self) -> bool {
+        matches!(self, BuildResult::InfrastructureFailure { .. })
+    }
+
+    pub fn details(&self) -> Option<String> {
+        match self {
+            BuildResult::CompileError { exit_code: Some(x) }
+            | BuildResult::CloneFailed { exit_code: Some(x) } => Some(format!("exit code {x}")),
+            BuildResult::CompilerPanic { message }
+            | BuildResult::DependencyFetchFailed { message }
+            | BuildResult::InfrastructureFailure { message } => Some(message.clone()),
+            BuildResult::Timeout { seconds } => Some(format!("{seconds} seconds")),
+            BuildResult::OutOfMemory { limit: Some(x) } => Some(format!("{x} bytes")),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for BuildResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            BuildResult::Success => "Success",
+            BuildResult::CompileError { .. } => "CompileError",
+            BuildResult::CompilerPanic { .. } => "CompilerPanic",
+            BuildResult::CloneFailed { .. } => "CloneFailed",
+            BuildResult::NoVerylToml => "NoVerylToml",
+            BuildResult::Timeout { .. } => "Timeout",
+            BuildResult::OutOfMemory { .. } => "OutOfMemory",
+            BuildResult::DependencyFetchFailed { .. } => "DependencyFetchFailed",
+            BuildResult::InfrastructureFailure { .. } => "InfrastructureFailure",
+        };
+        text.fmt(f)
+    }
+}
+
+// HashMap silently drops duplicated keys, so they are rejected here
+fn deserialize_projects<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<HashMap<u64, Project>, D::Error> {
+    struct ProjectsVisitor;
+
+    impl<'de> Visitor<'de> for ProjectsVisitor {
+        type Value = HashMap<u64, Project>;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a map of projects")
+        }
+
+        fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Self::Value, A::Error> {
+            let mut ret = HashMap::new();
+            while let Some((id, prj)) = map.next_entry::<u64, Project>()? {
+                if ret.insert(id, prj).is_some() {
+                    return Err(de::Error::custom(format!("duplicated project id {id}")));
+                }
+            }
+            Ok(ret)
+        }
+    }
+
+    deserializer.deserialize_map(ProjectsVisitor)
+}
+
+#[derive(Clone, Debug)]
+pub struct BuildLimits {
+    pub timeout: Duration,
+    /// Maximum virtual memory in bytes
+    pub memory: Option<u64>,
+    /// Maximum CPU time in seconds
+    pub cpu: Option<u64>,
+}
+
+impl Default for BuildLimits {
+    fn default() -> Self {
+        Self {
+            timeout: DEFAULT_BUILD_TIMEOUT,
+            memory: None,
+            cpu: None,
+        }
+    }
+}
+
+impl BuildLimits {
+    fn apply(&self, cmd: &mut std::process::Command) {
+        let memory = self.memory;
+        let cpu = self.cpu;
+        if memory.is_none() && cpu.is_none() {
+            return;
+        }
+
+        // SAFETY: only async-signal-safe setrlimit is called between fork and exec
+        unsafe {
+            cmd.pre_exec(move || {
+                if let Some(x) = memory {
+                    let limit = libc::rlimit {
+                        rlim_cur: x as libc::rlim_t,
+                        rlim_max: x as libc::rlim_t,
+                    };
+                    if libc::setrlimit(libc::RLIMIT_AS, &limit) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                if let Some(x) = cpu {
+                    // SIGXCPU is sent only if the soft limit is below the hard limit, otherwise SIGKILL
+                    let limit = libc::rlimit {
+                        rlim_cur: x as libc::rlim_t,
+                        rlim_max: (x + 1) as libc::rlim_t,
+                    };
+                    if libc::setrlimit(libc::RLIMIT_CPU, &limit) != 0 {
+                        return Err(std::io::Error::last_os_error());
+                    }
+                }
+                Ok(())
+            });
+        }
+    }
+
+    fn classify(&self, output: &Output) -> BuildResult {
+        if output.status.success() {
+            return BuildResult::Success;
+        }
+
+        if output.status.signal() == Some(libc::SIGXCPU) {
+            return BuildResult::Timeout {
+                seconds: self.cpu.unwrap_or(0),
+            };
+        }
+
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        // Rust runtime reports allocation failure before abort
+        if stderr.contains("memory allocation of") {
+            return BuildResult::OutOfMemory { limit: self.memory };
+        }
+
+        if let Some(line) = stderr.lines().find(|x| x.contains("panicked at")) {
+            // The panic message follows the location line
+            let mut lines = stderr.lines().skip_while(|x| *x != line);
+            let location = lines.next().unwrap_or_default();
+            let message = lines.next().unwrap_or_default();
+            return BuildResult::CompilerPanic {
+                message: format!("{} {}", location.trim(), message.trim())
+                    .trim()
+                    .to_string(),
+            };
+        }
+
+        if let Some(line) = stderr
+            .lines()
+            .find(|x| DEPENDENCY_ERRORS.iter().any(|y| x.contains(y)))
+        {
+            return BuildResult::DependencyFetchFailed {
+                message: line.trim().to_string(),
+            };
+        }
+
+        BuildResult::CompileError {
+            exit_code: output.status.code(),
+        }
+    }
 }
 
 #[derive(Clone, Copy, PartialEq, Eq)]
@@ -55,21 +446,20 @@ pub enum ReleaseKind {
 }
 
 impl Db {
-    pub fn load<T: AsRef<Path>>(path: T) -> Result<Db> {
-        let mut file = File::open(&path)?;
-        let mut buf = Vec::new();
-        file.read_to_end(&mut buf)?;
-        let db: Db = serde_json::from_str(&String::from_utf8(buf)?)?;
-        Ok(db)
+    pub fn new() -> Db {
+        Db {
+            schema_version: SCHEMA_VERSION,
+            ..Default::default()
+        }
     }
 
-    pub fn save<T: AsRef<Path>>(&self, path: T) -> Result<()> {
-        let mut file = File::create(&path)?;
-        let encoded: Vec<u8> = serde_json::to_string(&self)?.into_bytes();
-        file.write_all(&encoded)?;
-        file.flush()?;
+    /// Load DB from the storage selected by the file extension
+    pub fn load<T: AsRef<Path>>(path: T) -> Result<(Db, Vec<String>)> {
+        storage::open(path).load()
+    }
 
-        Ok(())
+    pub fn save<T: AsRef<Path>>(&self, path: T) -> Result<()> {
+        storage::open(path).save(self)
     }
 
     fn push_discovered(&mut self, discovered: Discovered) {
@@ -95,7 +485,8 @@ impl Db {
                 } else if name.ends_with("aarch64-mac.zip") {
                     Platform::Aarch64Mac
                 } else {
-                    unreachable!()
+                    // Checksums and other files
+                    continue;
                 };
                 counts.insert(platform, asset.download_count);
             }
@@ -128,34 +519,171 @@ impl Db {
     }
 
     pub fn insert_project(&mut self, prj: Project) -> u64 {
-        if let Some(id) = self.find_project(&prj.url) {
+        if let Some(id) = self.find_project(&prj.url, &prj.path) {
             id
         } else {
-            let id = self.projects.len() as u64;
+            let id = self.next_project_id;
+            self.next_project_id += 1;
             self.projects.insert(id, prj);
             id
         }
     }
 
-    pub fn find_project(&self, url: &Url) -> Option<u64> {
+    /// Check consistency of project IDs and return the found problems
+    pub fn validate(&self) -> Vec<String> {
+        let mut problems = vec![];
+
+        let mut ids: Vec<_> = self.projects.keys().collect();
+        ids.sort();
+
+        let mut names = HashMap::new();
+        for id in ids {
+            let prj = &self.projects[id];
+            if let Some(x) = names.insert(prj.name(), id) {
+                problems.push(format!("project {prj} is registered as both {x} and {id}"));
+            }
+            if *id >= self.next_project_id {
+                problems.push(format!(
+                    "project {id} is not less than next_project_id {}",
+                    self.next_project_id
+                ));
+            }
+        }
+
+        for discovered in &self.discovered {
+            let mut found = HashSet::new();
+            for id in &discovered.projects {
+                if !self.projects.contains_key(id) {
+                    problems.push(format!(
+                        "discovered at {} refers to unknown project {id}",
+                        discovered.date
+                    ));
+                }
+                if !found.insert(id) {
+                    problems.push(format!(
+                        "discovered at {} refers to project {id} twice",
+                        discovered.date
+                    ));
+                }
+            }
+        }
+
+        problems
+    }
+
+    /// Find project by ID, URL or repository name like "owner/repo" or "owner/repo/sub/dir".
+    /// A name matching several projects is an error.
+    pub fn find_project_by_name(&self, name: &str) -> Result<u64> {
+        if let Ok(id) = name.parse::<u64>() {
+            return if self.projects.contains_key(&id) {
+                Ok(id)
+            } else {
+                Err(anyhow!("project \"{name}\" is not found"))
+            };
+        }
+        let name = name.trim_end_matches('/');
+        let mut ids = vec![];
+        for (id, prj) in &self.projects {
+            let prj_name = prj.name();
+            if prj_name == name {
+                return Ok(*id);
+            }
+            if prj_name.ends_with(&format!("/{name}")) {
+                ids.push(*id);
+            }
+        }
+        ids.sort();
+        match ids.as_slice() {
+            [] => Err(anyhow!("project \"{name}\" is not found")),
+            [id] => Ok(*id),
+            _ => {
+                let candidates: Vec<_> = ids.iter().map(|x| self.projects[x].name()).collect();
+                Err(anyhow!(
+                    "project \"{name}\" is ambiguous: {}",
+                    candidates.join(", ")
+                ))
+            }
+        }
+    }
+
+    pub fn print_log(&self, opt: &OptLog) -> Result<()> {
+        let id = self.find_project_by_name(&opt.project)?;
+        let prj = &self.projects[&id];
+
+        let build_log = if let Some(run) = opt.run {
+            prj.build_logs.get(run)
+        } else {
+            prj.build_logs.last()
+        };
+        let build_log = build_log.ok_or_else(|| anyhow!("build log is not found"))?;
+
+        build_log.print(prj);
+
+        Ok(())
+    }
+
+    /// Move the repository root project to the first sub-project if the root has no Veryl.toml.
+    /// If the sub-project is already known, the history of the root is merged into it.
+    fn retire_root(&mut self, url: &Url, roots: &[String]) {
+        if roots.iter().any(|x| x.is_empty()) {
+            return;
+        }
+        let (Some(id), Some(first)) = (self.find_project(url, ""), roots.first()) else {
+            return;
+        };
+
+        if let Some(target) = self.find_project(url, first) {
+            // History of the root precedes the one of the sub-project registered later
+            let root = self.projects.remove(&id).unwrap();
+            let prj = self.projects.get_mut(&target).unwrap();
+            prj.build_logs.splice(0..0, root.build_logs);
+            prj.bisect_logs.splice(0..0, root.bisect_logs);
+            if prj.dependencies.is_empty() {
+                prj.dependencies = root.dependencies;
+            }
+            for discovered in &mut self.discovered {
+                for x in &mut discovered.projects {
+                    if *x == id {
+                        *x = target;
+                    }
+                }
+                discovered.projects.sort();
+                discovered.projects.dedup();
+            }
+        } else {
+            self.projects.get_mut(&id).unwrap().path = first.clone();
+        }
+    }
+
+    pub fn find_project(&self, url: &Url, path: &str) -> Option<u64> {
         for (id, prj) in &self.projects {
-            if url == &prj.url {
+            if url == &prj.url && path == prj.path {
                 return Some(*id);
             }
         }
         None
     }
 
-    async fn search(query: &str, retry: u32) -> Result<Page<Code>> {
-        let token = SecretString::from(std::env::var("GITHUB_TOKEN").unwrap());
-        let octocrab = octocrab::Octocrab::builder()
-            .personal_token(token)
-            .build()?;
+    fn github() -> Result<Octocrab> {
+        let token = SecretString::from(std::env::var("GITHUB_TOKEN")?);
+        let octocrab = Octocrab::builder().personal_token(token).build()?;
+        Ok(octocrab)
+    }
 
+    async fn search(octocrab: &Octocrab, query: &str, page: u32, retry: u32) -> Result<Page<Code>> {
         let mut duration = 30;
 
         for _ in 0..retry {
-            if let Ok(page) = octocrab.search().code(query).send().await {
+            let ret = octocrab
+                .search()
+                .code(query)
+                .per_page(SEARCH_PER_PAGE)
+                .page(page)
+                .send()
+                .await;
+            if let Ok(page) = ret {
+                // The code search API allows only a few requests per minute
+                time::sleep(SEARCH_INTERVAL).await;
                 return Ok(page);
             } else {
                 time::sleep(Duration::from_secs(duration)).await;
@@ -166,21 +694,76 @@ impl Db {
         Err(anyhow!("retry over"))
     }
 
+    /// Fetch all pages of the search result.
+    /// Queries hitting the result cap are split by file size until each range fits.
+    async fn search_all(octocrab: &Octocrab, query: &str) -> Result<SearchResult> {
+        let mut ret = SearchResult::default();
+        let mut ranges = vec![(0, SEARCH_MAX_FILE_SIZE)];
+
+        while let Some((min, max)) = ranges.pop() {
+            let sized_query = format!("{query} size:{min}..{max}");
+            let mut page = Self::search(octocrab, &sized_query, 1, 5).await?;
+            ret.pages += 1;
+
+            let total_count = page.total_count.unwrap_or(0);
+            if total_count > SEARCH_MAX_RESULTS && min < max {
+                let mid = min + (max - min) / 2;
+                ranges.push((mid + 1, max));
+                ranges.push((min, mid));
+                continue;
+            }
+
+            let last_page = total_count
+                .min(SEARCH_MAX_RESULTS)
+                .div_ceil(SEARCH_PER_PAGE as u64);
+            let mut page_num = 1;
+            loop {
+                let items = page.take_items();
+                if items.is_empty() {
+                    break;
+                }
+                ret.items.extend(items);
+
+                page_num += 1;
+                if page_num as u64 > last_page {
+                    break;
+                }
+                page = Self::search(octocrab, &sized_query, page_num, 5).await?;
+                ret.pages += 1;
+            }
+        }
+
+        Ok(ret)
+    }
+
     pub async fn update(&mut self) -> Result<()> {
-        let page = Self::search("extension:veryl", 5).await?;
+        let octocrab = Self::github()?;
+
+        let page = Self::search(&octocrab, "extension:veryl", 1, 5).await?;
         let sources = page.total_count.unwrap_or(0);
 
-        let mut page = Self::search("filename:Veryl.toml", 5).await?;
+        let result = Self::search_all(&octocrab, "filename:Veryl.toml").await?;
+        let pages = result.pages;
+        let items = result.items.len() as u64;
         let mut projects = HashSet::new();
 
-        let items = page.take_items();
-        for item in items {
+        for item in result.items {
             let repo = item.repository.full_name;
             if let Some(repo) = repo {
                 let url = Url::parse(&format!("https://github.com/{}", repo)).unwrap();
+                // The project root is the directory containing the found Veryl.toml
+                let path = item
+                    .path
+                    .strip_suffix("Veryl.toml")
+                    .unwrap_or_default()
+                    .trim_end_matches('/')
+                    .to_string();
                 let project = Project {
                     url,
+                    path,
                     build_logs: vec![],
+                    dependencies: vec![],
+                    bisect_logs: vec![],
                 };
                 let id = self.insert_project(project);
                 projects.insert(id);
@@ -194,6 +777,8 @@ impl Db {
             date: Utc::now(),
             sources,
             projects,
+            pages,
+            items,
         };
 
         self.push_discovered(discovered);
@@ -297,176 +882,1996 @@ impl Db {
         Ok(())
     }
 
-    pub async fn build<T: AsRef<Path>>(&mut self, path: T, opt: Option<OptCheck>) -> Result<()> {
+    /// Group projects by repository in ID order.
+    /// Sub-projects in the same repository are built from a single clone.
+    fn repos(&self) -> Vec<(Url, Vec<u64>)> {
+        let mut ids: Vec<_> = self.projects.keys().copied().collect();
+        ids.sort();
+
+        let mut repos: Vec<(Url, Vec<u64>)> = vec![];
+        for id in ids {
+            let url = &self.projects[&id].url;
+            if let Some((_, x)) = repos.iter_mut().find(|(x, _)| x == url) {
+                x.push(id);
+            } else {
+                repos.push((url.clone(), vec![id]));
+            }
+        }
+        repos
+    }
+
+    /// Build projects with the downloaded release if specified, or the local compiler
+    pub async fn build<T: AsRef<Path>>(
+        &mut self,
+        path: T,
+        jobs: usize,
+        release: Option<Release>,
+        opt: Option<OptCheck>,
+    ) -> Result<()> {
         let update_db = opt.is_none();
+        let show_log = opt.as_ref().map(|x| x.log).unwrap_or(false);
 
         let dir = path.as_ref();
+        prepare_build_dir(dir)?;
 
-        if !dir.exists() {
-            fs::create_dir(dir)?;
-        }
-        for entry in fs::read_dir(&dir)? {
-            let entry = entry?;
-            let path = entry.path();
+        let veryl = if let Some(release) = &release {
+            toolchain::install(dir, release).await?
+        } else {
+            local_veryl(opt.as_ref().and_then(|x| x.path.as_ref()))?
+        };
 
-            if entry.file_type()?.is_dir() {
-                fs::remove_dir_all(path)?;
-            } else {
-                fs::remove_file(path)?;
-            }
+        let version_arg = opt
+            .as_ref()
+            .and_then(|x| x.veryl_version.as_ref())
+            .map(|x| format!("+{x}"));
+        let limits = opt
+            .as_ref()
+            .map(|x| x.limits.to_limits())
+            .unwrap_or_default();
+        let mut ctx = BuildContext::new(dir, veryl, version_arg, update_db, limits).await?;
+        if let Some(tool) = opt.as_ref().and_then(|x| x.lint) {
+            let path = which::which(tool.binary()).map_err(|_| anyhow!("{tool} is not found"))?;
+            ctx.lint = Some((tool, path));
+        }
+        if let Some(steps) = opt.as_ref().and_then(|x| x.steps.clone()) {
+            ctx.steps = steps;
         }
+        let ctx = Arc::new(ctx);
 
-        let veryl = if let Some(opt) = &opt {
-            if let Some(path) = &opt.path {
-                path.canonicalize()?
-            } else {
-                which::which("veryl")?
-            }
-        } else {
-            let binary = reqwest::get(VERYL_BINARY).await?.bytes().await?;
-            zip_extract::extract(Cursor::new(binary), &dir, true)?;
-            let mut veryl = dir.to_path_buf();
-            veryl.push("veryl");
-            veryl.canonicalize()?
-        };
+        let repos = self.repos();
 
-        let version = Command::new(&veryl).arg("--version").output()?;
-        let version = String::from_utf8(version.stdout)?;
-        let version = version.replace("veryl ", "").trim().to_string();
-        let version = Version::parse(&version).unwrap();
+        let semaphore = Arc::new(Semaphore::new(jobs.max(1)));
+        let mut tasks = vec![];
+        let mut summary = BuildSummary::default();
+        let mut broken = vec![];
+        for (url, ids) in repos {
+            let mut history = HashMap::new();
+            let mut excluded = HashSet::new();
 
-        let mut build_logs = vec![];
-        for (id, prj) in &self.projects {
-            if !update_db {
+            for id in &ids {
+                let prj = &self.projects[id];
                 let latest_log = prj.build_logs.last();
+
                 if let Some(latest_log) = latest_log {
-                    if !latest_log.result && !opt.as_ref().unwrap().all {
-                        continue;
+                    let failed = !latest_log.result.is_success()
+                        && !latest_log.result.is_infrastructure_failure();
+                    if !update_db && failed && !opt.as_ref().unwrap().all {
+                        excluded.insert(prj.path.clone());
                     }
                 }
+                history.insert(
+                    prj.path.clone(),
+                    latest_log.map(|x| (x.rev.clone(), x.veryl_version.clone())),
+                );
             }
 
-            let path = prj.url.path().strip_prefix('/').unwrap();
-            let path = PathBuf::from(path);
+            if ids
+                .iter()
+                .all(|x| excluded.contains(&self.projects[x].path))
+            {
+                summary.skipped(SkipReason::PreviousFailure, ids.len());
+                continue;
+            }
 
-            let _ = Command::new("git")
-                .arg("clone")
-                .arg("--depth=1")
-                .arg(prj.url.as_str())
-                .arg(&path)
-                .current_dir(&dir)
-                .output()?;
+            let ctx = ctx.clone();
+            let semaphore = semaphore.clone();
+            let task_url = url.clone();
+            let task = tokio::spawn(async move {
+                let _permit = semaphore.acquire_owned().await?;
+                Self::build_repo(&ctx, &task_url, history, excluded).await
+            });
+            tasks.push((url, task));
+        }
 
-            let mut prj_dir = dir.to_path_buf();
-            prj_dir.push(&path);
+        // Await in project order so that the output is stable regardless of completion order
+        for (url, task) in tasks {
+            let repo_build = task.await??;
+            if let Some(roots) = &repo_build.roots {
+                self.retire_root(&url, roots);
+            }
+            for reason in repo_build.skipped {
+                summary.skipped(reason, 1);
+            }
+            for (path, dependencies) in repo_build.dependencies {
+                let id = self.insert_project(Project {
+                    url: url.clone(),
+                    path,
+                    build_logs: vec![],
+                    dependencies: vec![],
+                    bisect_logs: vec![],
+                });
+                self.projects.get_mut(&id).unwrap().dependencies = dependencies;
+            }
+            for (path, build_log) in repo_build.build_logs {
+                let id = self.insert_project(Project {
+                    url: url.clone(),
+                    path,
+                    build_logs: vec![],
+                    dependencies: vec![],
+                    bisect_logs: vec![],
+                });
+                let prj = self.projects.get_mut(&id).unwrap();
+
+                // Newly failing projects may break their dependents.
+                // Infrastructure failures don't tell anything about the project.
+                let was_success = prj
+                    .build_logs
+                    .iter()
+                    .rev()
+                    .find(|x| !x.result.is_infrastructure_failure())
+                    .map(|x| x.result.is_success());
+                let failed =
+                    !build_log.result.is_success() && !build_log.result.is_infrastructure_failure();
+                if failed && was_success != Some(false) {
+                    broken.push(id);
+                }
 
-            let rev = Command::new("git")
-                .arg("rev-parse")
-                .arg("HEAD")
-                .current_dir(&prj_dir)
-                .output()?;
-            let rev = String::from_utf8(rev.stdout)?.trim().to_string();
+                let color = if build_log.result.is_success() {
+                    Style::new().fg_color(Some(AnsiColor::BrightGreen.into()))
+                } else {
+                    Style::new().fg_color(Some(AnsiColor::BrightRed.into()))
+                };
+                println!("{color}{}{color:#}: {}", build_log.result, prj);
+                for step in build_log.steps.iter().filter(|x| !x.result.is_success()) {
+                    let color = Style::new().fg_color(Some(AnsiColor::BrightRed.into()));
+                    println!("  {color}{}{color:#}: {}", step.step, step.result);
+                }
+                if let Some(lint) = build_log.lint.as_ref().filter(|x| !x.result.is_success()) {
+                    let color = Style::new().fg_color(Some(AnsiColor::BrightRed.into()));
+                    println!("  {color}Lint{color:#}: {} by {}", lint.result, lint.tool);
+                }
+                if show_log && !build_log.is_clean() {
+                    build_log.print(prj);
+                }
 
-            if update_db {
-                let latest_log = prj.build_logs.last();
-                if let Some(latest_log) = latest_log {
-                    if latest_log.rev == rev && latest_log.veryl_version == version {
-                        continue;
-                    }
+                summary.built += 1;
+                if build_log.result.is_infrastructure_failure() {
+                    summary.infrastructure_failures += 1;
                 }
+                prj.build_logs.push(build_log);
             }
+        }
 
-            let mut veryl_root = None;
-            for entry in WalkDir::new(&prj_dir) {
-                let entry = entry?;
-                if entry.file_name() == "Veryl.toml" {
-                    veryl_root = Some(entry.path().parent().unwrap().to_path_buf());
+        summary.print();
+
+        let graph = Graph::new(self);
+        for id in broken {
+            let dependents = graph.dependents(id);
+            if !dependents.is_empty() {
+                println!(
+                    "Dependents of {} which may be affected:",
+                    self.projects[&id]
+                );
+                for x in dependents {
+                    println!("  {}", self.projects[&x]);
                 }
             }
+        }
 
-            let result = if let Some(veryl_root) = veryl_root {
-                let version_arg =
-                    if let Some(x) = opt.as_ref().map(|x| x.veryl_version.clone()).flatten() {
-                        Some(format!("+{x}"))
-                    } else {
-                        None
-                    };
+        Ok(())
+    }
 
-                let build = if let Some(x) = version_arg {
-                    Command::new(&veryl)
-                        .arg(x)
-                        .arg("build")
-                        .current_dir(&veryl_root)
-                        .output()?
-                } else {
-                    Command::new(&veryl)
-                        .arg("build")
-                        .current_dir(&veryl_root)
-                        .output()?
-                };
-                build.status.success()
-            } else {
-                false
-            };
+    /// Get the head of the default branch without cloning
+    async fn remote_head(url: &Url) -> Result<Option<String>> {
+        let output = git_remote(git().arg("ls-remote").arg(url.as_str()).arg("HEAD")).await?;
+        if !output.status.success() {
+            return Ok(None);
+        }
+        let output = String::from_utf8(output.stdout)?;
+        Ok(output.split_whitespace().next().map(|x| x.to_string()))
+    }
 
-            let build_log = BuildLog {
-                rev,
-                veryl_version: version.clone(),
-                result,
-            };
+    /// Build all Veryl projects in the repository.
+    /// Failures which don't belong to any sub-project are logged to all known projects.
+    async fn build_repo(
+        ctx: &BuildContext,
+        url: &Url,
+        history: HashMap<String, Option<(String, Version)>>,
+        excluded: HashSet<String>,
+    ) -> Result<RepoBuild> {
+        let mut ret = RepoBuild::default();
+
+        let is_unchanged = |rev: &str, path: &str| {
+            matches!(history.get(path), Some(Some((latest_rev, latest_version)))
+                if latest_rev == rev && *latest_version == ctx.version)
+        };
 
-            build_logs.push((*id, build_log));
+        if ctx.update_db {
+            if let Some(rev) = Self::remote_head(url).await? {
+                // A never-built root next to built sub-projects has no Veryl.toml, so it is ignored
+                let built = history.values().any(|x| x.is_some());
+                let paths: Vec<_> = history
+                    .iter()
+                    .filter(|(path, x)| !(path.is_empty() && x.is_none() && built))
+                    .map(|(path, _)| path)
+                    .collect();
+                if paths.iter().all(|x| is_unchanged(&rev, x)) {
+                    ret.skipped = vec![SkipReason::Unchanged; paths.len()];
+                    return Ok(ret);
+                }
+            }
+        }
 
-            if result {
-                let color = Style::new().fg_color(Some(AnsiColor::BrightGreen.into()));
-                println!("{color}Success{color:#}: {}", prj.url);
-            } else {
-                let color = Style::new().fg_color(Some(AnsiColor::BrightRed.into()));
-                println!("{color}Failure{color:#}: {}", prj.url);
+        let (repo_dir, rev) = match Self::checkout(ctx, url, None).await? {
+            Ok(x) => x,
+            Err(error) => {
+                let build_log = Self::checkout_failed(ctx, error);
+                let mut paths: Vec<_> = history.keys().cloned().collect();
+                paths.sort();
+                if paths.is_empty() {
+                    paths.push(String::new());
+                }
+                for path in paths {
+                    ret.build_logs.push((path, build_log.clone()));
+                }
+                return Ok(ret);
+            }
+        };
+
+        let mut veryl_roots = vec![];
+        for entry in WalkDir::new(&repo_dir)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_entry(|x| x.file_name() != ".git")
+        {
+            let entry = entry?;
+            if entry.file_name() == "Veryl.toml" {
+                let root = entry.path().parent().unwrap();
+                let rel = root.strip_prefix(&repo_dir)?;
+                let rel: Vec<_> = rel.iter().map(|x| x.to_string_lossy()).collect();
+                veryl_roots.push((rel.join("/"), root.to_path_buf()));
             }
         }
 
-        for (id, build_log) in build_logs {
-            self.projects
-                .entry(id)
-                .and_modify(|x| x.build_logs.push(build_log));
+        if veryl_roots.is_empty() {
+            veryl_roots.push((String::new(), repo_dir.clone()));
         }
+        ret.roots = Some(veryl_roots.iter().map(|(rel, _)| rel.clone()).collect());
 
-        Ok(())
-    }
-}
+        for (rel, root) in veryl_roots {
+            if let Ok(deps) = dependency::dependencies_in(&root) {
+                ret.dependencies.push((rel.clone(), deps));
+            }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Discovered {
-    #[serde(with = "ts_seconds")]
-    pub date: DateTime<Utc>,
-    pub sources: u64,
-    pub projects: Vec<u64>,
-}
+            if excluded.contains(&rel) {
+                ret.skipped.push(SkipReason::PreviousFailure);
+                continue;
+            }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
-pub struct Download {
-    #[serde(with = "ts_seconds")]
-    pub date: DateTime<Utc>,
-    pub counts: HashMap<Platform, u64>,
-}
+            if ctx.update_db && is_unchanged(&rev, &rel) {
+                ret.skipped.push(SkipReason::Unchanged);
+                continue;
+            }
 
-#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone)]
-pub enum Platform {
-    Aarch64Mac,
-    X86_64Linux,
-    X86_64Mac,
-    X86_64Windows,
+            let build_log = Self::build_root(ctx, &root, &rev).await?;
+            ret.build_logs.push((rel, build_log));
+        }
+
+        Ok(ret)
+    }
+
+    async fn build_root(ctx: &BuildContext, veryl_root: &Path, rev: &str) -> Result<BuildLog> {
+        let mut build_log = match Self::fetch_dependencies(ctx, veryl_root).await? {
+            Ok(git_config) => Self::build_project(ctx, veryl_root, &git_config).await?,
+            Err(result) => BuildLog {
+                rev: String::new(),
+                veryl_version: ctx.version.clone(),
+                result,
+                stdout: String::new(),
+                stderr: String::new(),
+                usage: None,
+                steps: vec![],
+                lint: None,
+            },
+        };
+        build_log.rev = rev.to_string();
+        Ok(build_log)
+    }
+
+    /// Check out and build a single project at the revision if specified, or the head of the default branch
+    async fn build_one(
+        ctx: &BuildContext,
+        url: &Url,
+        path: &str,
+        rev: Option<&str>,
+    ) -> Result<BuildLog> {
+        match Self::checkout(ctx, url, rev).await? {
+            Ok((repo_dir, rev)) => {
+                let root = if path.is_empty() {
+                    repo_dir
+                } else {
+                    repo_dir.join(path)
+                };
+                Self::build_root(ctx, &root, &rev).await
+            }
+            Err(error) => Ok(Self::checkout_failed(ctx, error)),
+        }
+    }
+
+    fn checkout_failed(ctx: &BuildContext, error: GitError) -> BuildLog {
+        let result = if error.exhausted {
+            BuildResult::InfrastructureFailure {
+                message: error.reason(),
+            }
+        } else {
+            BuildResult::CloneFailed {
+                exit_code: error.output.status.code(),
+            }
+        };
+        BuildLog {
+            rev: String::new(),
+            veryl_version: ctx.version.clone(),
+            result,
+            stdout: String::new(),
+            stderr: excerpt(&error.output.stderr),
+            usage: None,
+            steps: vec![],
+            lint: None,
+        }
+    }
+
+    /// Build all projects with the compiler without updating DB.
+    /// Projects in the same repository are built sequentially because they share a worktree.
+    /// Generated SystemVerilog of successful builds is kept in `outputs/<project ID>` if specified.
+    /// Projects are checked out at the revisions in `revs` so that several runs build the same code.
+    async fn build_each(
+        &self,
+        ctx: Arc<BuildContext>,
+        jobs: usize,
+        outputs: Option<PathBuf>,
+        revs: &HashMap<u64, String>,
+        excluded: &HashSet<u64>,
+    ) -> Result<HashMap<u64, BuildLog>> {
+        let semaphore = Arc::new(Semaphore::new(jobs.max(1)));
+        let mut tasks = vec![];
+        for (url, ids) in self.repos() {
+            let paths: Vec<_> = ids
+                .iter()
+                .filter(|x| !excluded.contains(x))
+                .map(|x| (*x, self.projects[x].path.clone(), revs.get(x).cloned()))
+                .collect();
+            if paths.is_empty() {
+                continue;
+            }
+            let ctx = ctx.clone();
+            let semaphore = semaphore.clone();
+            let outputs = outputs.clone();
+            let task = tokio::spawn(async move {
+                let _permit = semaphore.acquire_owned().await?;
+                let mut ret = vec![];
+                for (id, path, rev) in paths {
+                    let build_log = Self::build_one(&ctx, &url, &path, rev.as_deref()).await?;
+                    if let Some(outputs) = &outputs {
+                        if build_log.result.is_success() {
+                            let root = ctx.work_path(&url).join(&path);
+                            output::collect(&root, &outputs.join(id.to_string()))?;
+                        }
+                    }
+                    ret.push((id, build_log));
+                }
+                anyhow::Ok(ret)
+            });
+            tasks.push(task);
+        }
+
+        let mut ret = HashMap::new();
+        for task in tasks {
+            ret.extend(task.await??);
+        }
+        Ok(ret)
+    }
+
+    /// Check the formatter against projects which built successfully at the latest build,
+    /// and return the number of projects with problems
+    pub async fn fmt_check<T: AsRef<Path>>(
+        &self,
+        path: T,
+        jobs: usize,
+        opt: &OptFmt,
+    ) -> Result<usize> {
+        let dir = path.as_ref();
+        prepare_build_dir(dir)?;
+
+        let veryl = local_veryl(opt.path.as_ref())?;
+        let version_arg = opt.veryl_version.as_ref().map(|x| format!("+{x}"));
+        let ctx = BuildContext::new(dir, veryl, version_arg, false, opt.limits.to_limits()).await?;
+        let ctx = Arc::new(ctx);
+
+        let semaphore = Arc::new(Semaphore::new(jobs.max(1)));
+        let mut tasks = vec![];
+        for (url, ids) in self.repos() {
+            let paths: Vec<_> = ids
+                .iter()
+                .filter(|x| {
+                    let latest = self.projects[x].build_logs.last();
+                    latest.is_some_and(|x| x.result.is_success())
+                })
+                .map(|x| (*x, self.projects[x].path.clone()))
+                .collect();
+            if paths.is_empty() {
+                continue;
+            }
+
+            let ctx = ctx.clone();
+            let semaphore = semaphore.clone();
+            let task = tokio::spawn(async move {
+                let _permit = semaphore.acquire_owned().await?;
+                let mut ret = vec![];
+                for (id, path) in paths {
+                    let check = match Self::checkout(&ctx, &url, None).await? {
+                        Ok((repo_dir, _)) => {
+                            let root = repo_dir.join(&path);
+                            match Self::fetch_dependencies(&ctx, &root).await? {
+                                Ok(git_config) => {
+                                    Self::fmt_project(&ctx, &root, &git_config).await?
+                                }
+                                Err(result) => FmtCheck::Unavailable {
+                                    message: result.details().unwrap_or_default(),
+                                },
+                            }
+                        }
+                        Err(error) => FmtCheck::Unavailable {
+                            message: error.reason(),
+                        },
+                    };
+                    ret.push((id, check));
+                }
+                anyhow::Ok(ret)
+            });
+            tasks.push(task);
+        }
+
+        let mut problems = 0;
+        let mut checked = 0;
+        for task in tasks {
+            for (id, check) in task.await?? {
+                let prj = &self.projects[&id];
+                let color = if check == FmtCheck::Success {
+                    Style::new().fg_color(Some(AnsiColor::BrightGreen.into()))
+                } else {
+                    Style::new().fg_color(Some(AnsiColor::BrightRed.into()))
+                };
+                println!("{color}{check}{color:#}: {prj}");
+
+                match &check {
+                    FmtCheck::Success => (),
+                    FmtCheck::Unavailable { message } => {
+                        println!("  {}", message.trim());
+                        continue;
+                    }
+                    FmtCheck::FormatFailed { stderr, .. } => {
+                        if opt.log {
+                            println!("{}", stderr.trim_end());
+                        }
+                    }
+                    FmtCheck::NotIdempotent { files } => {
+                        for file in files {
+                            println!("    {file}");
+                        }
+                    }
+                    FmtCheck::BuildFailed { files, stderr, .. } => {
+                        for file in files {
+                            println!("    {file}");
+                        }
+                        if opt.log {
+                            println!("{}", stderr.trim_end());
+                        }
+                    }
+                }
+
+                checked += 1;
+                if check != FmtCheck::Success {
+                    problems += 1;
+                }
+            }
+        }
+
+        println!("Checked {checked} projects, {problems} projects have formatter problems");
+        Ok(problems)
+    }
+
+    /// Report projects whose compile time or memory usage grew against the previous Veryl version
+    pub fn perf(&self, opt: &OptPerf) {
+        let mut ids: Vec<_> = self.projects.keys().copied().collect();
+        ids.sort();
+
+        let growth = |old: f64, new: f64| (new - old) / old * 100.0;
+
+        let mut regressions = 0;
+        for id in ids {
+            let prj = &self.projects[&id];
+            let mut logs = prj
+                .build_logs
+                .iter()
+                .rev()
+                .filter(|x| x.result.is_success())
+                .filter_map(|x| x.usage.map(|y| (&x.veryl_version, y)));
+            let Some((new_version, new)) = logs.next() else {
+                continue;
+            };
+            let Some((old_version, old)) = logs.find(|(x, _)| *x < new_version) else {
+                continue;
+            };
+
+            let mut problems = vec![];
+            if old.cpu_time() > 0.0 {
+                let x = growth(old.cpu_time(), new.cpu_time());
+                if x > opt.threshold {
+                    problems.push(format!(
+                        "CPU time {:.2}s -> {:.2}s (+{x:.1}%)",
+                        old.cpu_time(),
+                        new.cpu_time()
+                    ));
+                }
+            }
+            if old.max_rss > 0 {
+                let x = growth(old.max_rss as f64, new.max_rss as f64);
+                if x > opt.threshold {
+                    problems.push(format!(
+                        "max RSS {} KiB -> {} KiB (+{x:.1}%)",
+                        old.max_rss / 1024,
+                        new.max_rss / 1024
+                    ));
+                }
+            }
+
+            if !problems.is_empty() {
+                println!(
+                    "{prj} ({old_version} -> {new_version}): {}",
+                    problems.join(", ")
+                );
+                regressions += 1;
+            }
+        }
+
+        println!(
+            "{regressions} projects grew by more than {}%",
+            opt.threshold
+        );
+    }
+
+    /// Build all projects several times from scratch, and return the number of projects
+    /// whose results or generated SystemVerilog differ between runs
+    pub async fn check_determinism<T: AsRef<Path>>(
+        &self,
+        path: T,
+        jobs: usize,
+        opt: &OptCheck,
+    ) -> Result<usize> {
+        let dir = path.as_ref();
+        prepare_build_dir(dir)?;
+
+        let veryl = if let Some(release) = opt.to_release() {
+            toolchain::install(dir, &release).await?
+        } else {
+            local_veryl(opt.path.as_ref())?
+        };
+        let version_arg = opt.veryl_version.as_ref().map(|x| format!("+{x}"));
+        let limits = opt.limits.to_limits();
+        let mut ctx = BuildContext::new(dir, veryl, version_arg, false, limits).await?;
+        if let Some(steps) = &opt.steps {
+            ctx.steps = steps.clone();
+        }
+        if let Some(tool) = opt.lint {
+            let path = which::which(tool.binary()).map_err(|_| anyhow!("{tool} is not found"))?;
+            ctx.lint = Some((tool, path));
+        }
+        let ctx = Arc::new(ctx);
+
+        // Previously failing projects are skipped as `check` does
+        let excluded: HashSet<_> = self
+            .projects
+            .iter()
+            .filter(|(_, prj)| {
+                let latest = prj.build_logs.last();
+                !opt.all
+                    && latest.is_some_and(|x| {
+                        !x.result.is_success() && !x.result.is_infrastructure_failure()
+                    })
+            })
+            .map(|(id, _)| *id)
+            .collect();
+
+        let output_dir = dir.join(OUTPUT_DIR);
+        if output_dir.exists() {
+            fs::remove_dir_all(&output_dir)?;
+        }
+
+        let mut runs: Vec<(PathBuf, HashMap<u64, BuildLog>)> = vec![];
+        for i in 0..opt.repeat {
+            println!("Run {}/{}", i + 1, opt.repeat);
+            let outputs = output_dir.join(format!("run{i}"));
+            // Later runs build the revisions of the first run
+            let revs = runs.first().map(|x| revs_of(&x.1)).unwrap_or_default();
+            let results = self
+                .build_each(ctx.clone(), jobs, Some(outputs.clone()), &revs, &excluded)
+                .await?;
+            runs.push((outputs, results));
+        }
+
+        let mut ids: Vec<_> = self.projects.keys().copied().collect();
+        ids.sort();
+
+        let mut nondeterministic = 0;
+        for id in ids {
+            let mut results = vec![];
+            for (outputs, logs) in &runs {
+                let Some(log) = logs.get(&id) else {
+                    continue;
+                };
+                if log.result.is_infrastructure_failure() {
+                    continue;
+                }
+                let hash = output::hash(&outputs.join(id.to_string()))?;
+                let lint = log.lint.as_ref().map(|x| &x.result);
+                results.push((&log.rev, (&log.result, lint), hash));
+            }
+
+            if results.windows(2).all(|x| x[0] == x[1]) {
+                continue;
+            }
+            // Changes of the project itself are not nondeterminism of the compiler
+            if results.windows(2).any(|x| x[0].0 != x[1].0) {
+                println!("Revision changed: {}", self.projects[&id]);
+                continue;
+            }
+
+            let red = Style::new().fg_color(Some(AnsiColor::BrightRed.into()));
+            println!("{red}Nondeterministic{red:#}: {}", self.projects[&id]);
+            for (i, (_, (result, lint), hash)) in results.iter().enumerate() {
+                let hash = hash.as_ref().map(|x| &x[..12]).unwrap_or("-");
+                if let Some(lint) = lint {
+                    println!("  run {}: {result}, lint {lint} (outputs {hash})", i + 1);
+                } else {
+                    println!("  run {}: {result} (outputs {hash})", i + 1);
+                }
+            }
+            if opt.log {
+                for (_, logs) in &runs {
+                    if let Some(log) = logs.get(&id).filter(|x| !x.is_clean()) {
+                        log.print(&self.projects[&id]);
+                    }
+                }
+            }
+            nondeterministic += 1;
+        }
+
+        if !excluded.is_empty() {
+            println!(
+                "Skipped {} projects: failed previously (use --all to build)",
+                excluded.len()
+            );
+        }
+        println!(
+            "{nondeterministic} projects are nondeterministic in {} runs",
+            opt.repeat
+        );
+        Ok(nondeterministic)
+    }
+
+    /// Build all projects with two compilers, and return the number of newly failing projects
+    pub async fn compare<T: AsRef<Path>>(
+        &self,
+        path: T,
+        jobs: usize,
+        opt: &OptCompare,
+    ) -> Result<usize> {
+        let dir = path.as_ref();
+        prepare_build_dir(dir)?;
+        let limits = opt.limits.to_limits();
+
+        let output_dir = dir.join(OUTPUT_DIR);
+        if output_dir.exists() {
+            fs::remove_dir_all(&output_dir)?;
+        }
+
+        let mut results: Vec<HashMap<u64, BuildLog>> = vec![];
+        for (name, source) in [("base", &opt.base), ("target", &opt.target)] {
+            let (veryl, version_arg) = compiler_source(source)?;
+            let ctx = BuildContext::new(dir, veryl, version_arg, false, limits.clone()).await?;
+            println!("Building with {source} (veryl {})", ctx.version);
+            let outputs = opt.outputs.then(|| output_dir.join(name));
+
+            // The target compiler builds the revisions built by the base one
+            let revs = results.first().map(revs_of).unwrap_or_default();
+            let ctx = Arc::new(ctx);
+            results.push(
+                self.build_each(ctx, jobs, outputs, &revs, &HashSet::new())
+                    .await?,
+            );
+        }
+        let (base, target) = (&results[0], &results[1]);
+
+        let mut ids: Vec<_> = self.projects.keys().copied().collect();
+        ids.sort();
+
+        let mut newly_failing = vec![];
+        let mut newly_passing = vec![];
+        let mut unchanged = 0;
+        let mut unavailable = 0;
+        let mut rev_changed = vec![];
+        let mut output_diffs = vec![];
+        for id in ids {
+            let (Some(base), Some(target)) = (base.get(&id), target.get(&id)) else {
+                continue;
+            };
+            if base.result.is_infrastructure_failure() || target.result.is_infrastructure_failure()
+            {
+                unavailable += 1;
+                continue;
+            }
+            // Changes of the project itself are not regressions of the compiler
+            if base.rev != target.rev {
+                rev_changed.push(id);
+                continue;
+            }
+            match (base.result.is_success(), target.result.is_success()) {
+                (true, false) => newly_failing.push((id, target)),
+                (false, true) => newly_passing.push((id, base)),
+                (true, true) if opt.outputs => {
+                    let id_dir = id.to_string();
+                    let diff = output::diff(
+                        &output_dir.join("base").join(&id_dir),
+                        &output_dir.join("target").join(&id_dir),
+                    )?;
+                    output_diffs.push((id, diff));
+                    unchanged += 1;
+                }
+                _ => unchanged += 1,
+            }
+        }
+
+        let red = Style::new().fg_color(Some(AnsiColor::BrightRed.into()));
+        let green = Style::new().fg_color(Some(AnsiColor::BrightGreen.into()));
+        println!(
+            "{red}Newly failing{red:#}: {} projects",
+            newly_failing.len()
+        );
+        for (id, log) in &newly_failing {
+            println!("  {}: {}", self.projects[id], log.result);
+            if opt.log {
+                log.print(&self.projects[id]);
+            }
+        }
+        println!(
+            "{green}Newly passing{green:#}: {} projects",
+            newly_passing.len()
+        );
+        for (id, log) in &newly_passing {
+            println!("  {} (was {})", self.projects[id], log.result);
+        }
+        println!("Unchanged: {unchanged} projects");
+        if unavailable != 0 {
+            println!("Not compared due to infrastructure failures: {unavailable} projects");
+        }
+        if !rev_changed.is_empty() {
+            println!(
+                "Not compared due to revision changes: {} projects",
+                rev_changed.len()
+            );
+            for id in &rev_changed {
+                println!("  {}", self.projects[id]);
+            }
+        }
+
+        if opt.outputs {
+            output_diffs.retain(|(_, x)| !x.is_empty());
+            println!("Output differences: {} projects", output_diffs.len());
+            for (id, diff) in &output_diffs {
+                println!(
+                    "  {}: {} changed, {} added, {} removed",
+                    self.projects[id],
+                    diff.changed.len(),
+                    diff.added.len(),
+                    diff.removed.len()
+                );
+                for (file, text) in &diff.changed {
+                    if opt.diff {
+                        print!("{text}");
+                    } else {
+                        println!("    M {file}");
+                    }
+                }
+                for file in &diff.added {
+                    println!("    A {file}");
+                }
+                for file in &diff.removed {
+                    println!("    D {file}");
+                }
+            }
+        }
+
+        Ok(newly_failing.len())
+    }
+
+    /// Find the first Veryl release which fails to build the project
+    pub async fn bisect<T: AsRef<Path>>(&mut self, path: T, opt: &OptBisect) -> Result<()> {
+        let id = self.find_project_by_name(&opt.project)?;
+
+        if opt.good >= opt.bad {
+            return Err(anyhow!("good version must be older than bad version"));
+        }
+
+        // Candidates are known releases in (good, bad]
+        let mut versions: Vec<_> = self
+            .veryl_downloads
+            .keys()
+            .filter(|x| opt.good < **x && **x < opt.bad)
+            .cloned()
+            .collect();
+        versions.push(opt.bad.clone());
+        versions.sort();
+
+        let dir = path.as_ref();
+        prepare_build_dir(dir)?;
+        let veryl = which::which("veryl")?;
+        let limits = opt.limits.to_limits();
+
+        let mut build = async |version: &Version| -> Result<bool> {
+            let version_arg = Some(format!("+{version}"));
+            let ctx =
+                BuildContext::new(dir, veryl.clone(), version_arg, false, limits.clone()).await?;
+            let prj = &self.projects[&id];
+            let build_log = Self::bisect_build(&ctx, prj, &version.to_string()).await?;
+            let success = build_log.result.is_success();
+            self.projects
+                .get_mut(&id)
+                .unwrap()
+                .bisect_logs
+                .push(build_log);
+            Ok(success)
+        };
+
+        // Check the endpoints so that the result is meaningful
+        if build(&opt.bad).await? {
+            return Err(anyhow!("project builds with bad version {}", opt.bad));
+        }
+        if !build(&opt.good).await? {
+            return Err(anyhow!("project fails with good version {}", opt.good));
+        }
+
+        let mut lo = 0;
+        let mut hi = versions.len() - 1;
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if build(&versions[mid]).await? {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+
+        println!(
+            "First failing version of {}: {}",
+            self.projects[&id], versions[hi]
+        );
+
+        Ok(())
+    }
+
+    /// Build the project for bisection and print the result
+    async fn bisect_build(ctx: &BuildContext, prj: &Project, target: &str) -> Result<BuildLog> {
+        let build_log = Self::build_one(ctx, &prj.url, &prj.path, None).await?;
+        if build_log.result.is_infrastructure_failure() {
+            return Err(anyhow!(
+                "{}",
+                build_log.result.details().unwrap_or_default()
+            ));
+        }
+
+        let color = if build_log.result.is_success() {
+            Style::new().fg_color(Some(AnsiColor::BrightGreen.into()))
+        } else {
+            Style::new().fg_color(Some(AnsiColor::BrightRed.into()))
+        };
+        println!(
+            "{color}{}{color:#}: {} with {}",
+            build_log.result, prj, target
+        );
+
+        Ok(build_log)
+    }
+
+    /// Find the first commit of the local Veryl repository which fails to build the project
+    pub async fn bisect_commit<T: AsRef<Path>>(
+        &self,
+        path: T,
+        opt: &OptBisectCommit,
+    ) -> Result<()> {
+        let id = self.find_project_by_name(&opt.project)?;
+        let prj = &self.projects[&id];
+        let repo = opt.repo.canonicalize()?;
+
+        let rev_list = git()
+            .arg("rev-list")
+            .arg("--first-parent")
+            .arg("--reverse")
+            .arg(format!("{}..{}", opt.good, opt.bad))
+            .current_dir(&repo)
+            .output()
+            .await?;
+        if !rev_list.status.success() {
+            return Err(anyhow!(
+                "failed to list commits: {}",
+                String::from_utf8_lossy(&rev_list.stderr).trim()
+            ));
+        }
+        let commits: Vec<_> = String::from_utf8(rev_list.stdout)?
+            .lines()
+            .map(|x| x.to_string())
+            .collect();
+        if commits.is_empty() {
+            return Err(anyhow!("no commits between {} and {}", opt.good, opt.bad));
+        }
+
+        let dir = path.as_ref();
+        prepare_build_dir(dir)?;
+        let limits = opt.limits.to_limits();
+
+        let good = git()
+            .arg("rev-parse")
+            .arg("--verify")
+            .arg(format!("{}^{{commit}}", opt.good))
+            .current_dir(&repo)
+            .output()
+            .await?;
+        let good = String::from_utf8(good.stdout)?.trim().to_string();
+
+        let build = async |commit: &str| -> Result<bool> {
+            let veryl = Self::build_compiler(dir, &repo, commit).await?;
+            let ctx = BuildContext::new(dir, veryl, None, false, limits.clone()).await?;
+            let build_log = Self::bisect_build(&ctx, prj, commit).await?;
+            Ok(build_log.result.is_success())
+        };
+
+        // Check the endpoints so that the result is meaningful
+        if build(commits.last().unwrap()).await? {
+            return Err(anyhow!("project builds with bad commit {}", opt.bad));
+        }
+        if !build(&good).await? {
+            return Err(anyhow!("project fails with good commit {}", opt.good));
+        }
+
+        let mut lo = 0;
+        let mut hi = commits.len() - 1;
+        while lo < hi {
+            let mid = (lo + hi) / 2;
+            if build(&commits[mid]).await? {
+                lo = mid + 1;
+            } else {
+                hi = mid;
+            }
+        }
+
+        let subject = git()
+            .arg("log")
+            .arg("-1")
+            .arg("--format=%s")
+            .arg(&commits[hi])
+            .current_dir(&repo)
+            .output()
+            .await?;
+        let subject = String::from_utf8_lossy(&subject.stdout);
+        println!(
+            "First failing commit of {prj}: {} {}",
+            commits[hi],
+            subject.trim()
+        );
+
+        Ok(())
+    }
+
+    /// Build the compiler at the commit of the local Veryl repository.
+    /// Built binaries are cached by commit hash.
+    async fn build_compiler(dir: &Path, repo: &Path, commit: &str) -> Result<PathBuf> {
+        let compilers = dir.canonicalize()?.join(COMPILER_DIR);
+        let veryl = compilers.join(commit).join("veryl");
+        if veryl.exists() {
+            return Ok(veryl);
+        }
+
+        let src = compilers.join("src");
+        if src.exists() {
+            fs::remove_dir_all(&src)?;
+        }
+        let _ = git()
+            .arg("worktree")
+            .arg("prune")
+            .current_dir(repo)
+            .output()
+            .await?;
+        let worktree = git()
+            .arg("worktree")
+            .arg("add")
+            .arg("--force")
+            .arg("--detach")
+            .arg(&src)
+            .arg(commit)
+            .current_dir(repo)
+            .output()
+            .await?;
+        if !worktree.status.success() {
+            return Err(anyhow!(
+                "failed to check out {commit}: {}",
+                String::from_utf8_lossy(&worktree.stderr).trim()
+            ));
+        }
+
+        println!("Building compiler at {commit}");
+        let target = compilers.join("target");
+        let build = Command::new("cargo")
+            .arg("build")
+            .arg("--release")
+            .arg("--bin")
+            .arg("veryl")
+            .env("CARGO_TARGET_DIR", &target)
+            .current_dir(&src)
+            .output()
+            .await?;
+        if !build.status.success() {
+            return Err(anyhow!(
+                "failed to build compiler at {commit}:\n{}",
+                excerpt(&build.stderr).trim()
+            ));
+        }
+
+        fs::create_dir_all(veryl.parent().unwrap())?;
+        fs::copy(target.join("release").join("veryl"), &veryl)?;
+
+        Ok(veryl)
+    }
+
+    /// Update the clone cache of the repository, and check out the head of the default branch
+    /// into a clean worktree. The failed git command is returned as `Err`.
+    async fn checkout(
+        ctx: &BuildContext,
+        url: &Url,
+        rev: Option<&str>,
+    ) -> Result<std::result::Result<(PathBuf, String), GitError>> {
+        let cache = ctx.cache_path(url);
+        let work = ctx.work_path(url);
+
+        let _guard = ctx.lock_cache(&cache).await;
+
+        if work.exists() {
+            fs::remove_dir_all(&work)?;
+        }
+
+        // The specified revision is used without fetching if the cache already has it
+        let cached = match rev {
+            Some(rev) => git()
+                .arg("cat-file")
+                .arg("-e")
+                .arg(format!("{rev}^{{commit}}"))
+                .current_dir(&cache)
+                .output()
+                .await
+                .is_ok_and(|x| x.status.success()),
+            None => false,
+        };
+
+        // FETCH_HEAD points the head of the default branch even if it was renamed
+        if !cached {
+            if let Err(error) = Self::fetch_cache(url, &cache, &["HEAD"]).await? {
+                return Ok(Err(error));
+            }
+        }
+
+        let rev = if let Some(rev) = rev {
+            rev.to_string()
+        } else {
+            let rev = git()
+                .arg("rev-parse")
+                .arg("FETCH_HEAD")
+                .current_dir(&cache)
+                .output()
+                .await?;
+            String::from_utf8(rev.stdout)?.trim().to_string()
+        };
+
+        let _ = git()
+            .arg("worktree")
+            .arg("prune")
+            .current_dir(&cache)
+            .output()
+            .await?;
+
+        let worktree = git()
+            .arg("worktree")
+            .arg("add")
+            .arg("--force")
+            .arg("--detach")
+            .arg(&work)
+            .arg(&rev)
+            .current_dir(&cache)
+            .output()
+            .await?;
+        if !worktree.status.success() {
+            return Ok(Err(GitError {
+                output: worktree,
+                exhausted: false,
+            }));
+        }
+
+        Ok(Ok((work, rev)))
+    }
+
+    /// Clone the repository into the cache if it doesn't exist, and fetch the refspecs.
+    /// Network errors are retried with exponential backoff.
+    /// The failed git command is returned as `Err`.
+    async fn fetch_cache(
+        url: &Url,
+        cache: &Path,
+        refspecs: &[&str],
+    ) -> Result<std::result::Result<(), GitError>> {
+        let mut interval = FETCH_RETRY_INTERVAL;
+        for i in 1..=FETCH_RETRY {
+            let output = match Self::fetch_cache_once(url, cache, refspecs).await? {
+                Ok(()) => return Ok(Ok(())),
+                Err(output) => output,
+            };
+
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let network = NETWORK_ERRORS.iter().any(|x| stderr.contains(x));
+            if !network || i == FETCH_RETRY {
+                return Ok(Err(GitError {
+                    output,
+                    exhausted: network,
+                }));
+            }
+
+            time::sleep(interval).await;
+            interval *= 2;
+        }
+        unreachable!()
+    }
+
+    async fn fetch_cache_once(
+        url: &Url,
+        cache: &Path,
+        refspecs: &[&str],
+    ) -> Result<std::result::Result<(), Output>> {
+        if !cache.exists() {
+            let clone = git_remote(
+                git()
+                    .arg("clone")
+                    .arg("--bare")
+                    .arg(url.as_str())
+                    .arg(cache),
+            )
+            .await?;
+            if !clone.status.success() {
+                if cache.exists() {
+                    fs::remove_dir_all(cache)?;
+                }
+                return Ok(Err(clone));
+            }
+        }
+
+        let fetch = git_remote(
+            git()
+                .arg("fetch")
+                .arg("--quiet")
+                .arg(url.as_str())
+                .args(refspecs)
+                .current_dir(cache),
+        )
+        .await?;
+        if !fetch.status.success() {
+            return Ok(Err(fetch));
+        }
+
+        Ok(Ok(()))
+    }
+
+    /// Fetch git dependencies declared in Veryl.toml into the clone cache recursively,
+    /// and return git configs which redirect them to the cache.
+    /// The result representing the failure is returned as `Err`.
+    async fn fetch_dependencies(
+        ctx: &BuildContext,
+        veryl_root: &Path,
+    ) -> Result<std::result::Result<Vec<(String, String)>, BuildResult>> {
+        // Missing Veryl.toml is reported by build_project
+        if !veryl_root.join("Veryl.toml").exists() {
+            return Ok(Ok(vec![]));
+        }
+
+        let mut queue = match dependency::dependencies_in(veryl_root) {
+            Ok(x) => x,
+            Err(x) => {
+                return Ok(Err(BuildResult::DependencyFetchFailed {
+                    message: format!("failed to parse Veryl.toml: {x}"),
+                }))
+            }
+        };
+
+        let mut visited = HashSet::new();
+        let mut git_config = vec![];
+        while let Some(url) = queue.pop() {
+            if !visited.insert(url.clone()) {
+                continue;
+            }
+
+            let cache = ctx.cache_path(&url);
+            let _guard = ctx.lock_cache(&cache).await;
+
+            let refspecs = ["+refs/heads/*:refs/heads/*", "+refs/tags/*:refs/tags/*"];
+            if let Err(error) = Self::fetch_cache(&url, &cache, &refspecs).await? {
+                let message = format!("failed to fetch {url}: {}", error.reason());
+                if error.exhausted {
+                    return Ok(Err(BuildResult::InfrastructureFailure { message }));
+                } else {
+                    return Ok(Err(BuildResult::DependencyFetchFailed { message }));
+                }
+            }
+
+            // Dependencies of the dependency at the default branch
+            let show = git()
+                .arg("show")
+                .arg("HEAD:Veryl.toml")
+                .current_dir(&cache)
+                .output()
+                .await?;
+            if show.status.success() {
+                if let Ok(deps) = dependency::dependencies(&String::from_utf8_lossy(&show.stdout)) {
+                    queue.extend(deps);
+                }
+            }
+
+            let Ok(cache_url) = Url::from_file_path(&cache) else {
+                continue;
+            };
+            let key = format!("url.{cache_url}.insteadOf");
+            git_config.push((key.clone(), format!("{url}.git")));
+            git_config.push((key, url.to_string()));
+        }
+
+        Ok(Ok(git_config))
+    }
+
+    /// Remove clone caches of repositories which are not in DB
+    pub fn gc<T: AsRef<Path>>(&self, path: T) -> Result<()> {
+        let cache_dir = path.as_ref().join(CACHE_DIR);
+        if !cache_dir.exists() {
+            return Ok(());
+        }
+
+        // Caches of dependencies are shared with projects, and kept as well
+        let known: HashSet<_> = self
+            .projects
+            .values()
+            .flat_map(|x| {
+                let deps = x.dependencies.iter().cloned().map(dependency::normalize);
+                std::iter::once(x.url.clone()).chain(deps)
+            })
+            .map(|x| format!("{}.git", repo_path(&x)))
+            .collect();
+
+        let mut removed = vec![];
+        let mut walker = WalkDir::new(&cache_dir).min_depth(1).into_iter();
+        while let Some(entry) = walker.next() {
+            let entry = entry?;
+            if !entry.file_type().is_dir() || !entry.file_name().to_string_lossy().ends_with(".git")
+            {
+                continue;
+            }
+            walker.skip_current_dir();
+
+            let rel = entry.path().strip_prefix(&cache_dir)?;
+            let rel: Vec<_> = rel.iter().map(|x| x.to_string_lossy()).collect();
+            if !known.contains(&rel.join("/")) {
+                removed.push(entry.path().to_path_buf());
+            }
+        }
+
+        for path in removed {
+            fs::remove_dir_all(&path)?;
+            println!("Removed: {}", path.display());
+        }
+
+        let work_dir = path.as_ref().join(WORK_DIR);
+        if work_dir.exists() {
+            fs::remove_dir_all(&work_dir)?;
+        }
+
+        Ok(())
+    }
+
+    async fn build_project(
+        ctx: &BuildContext,
+        veryl_root: &Path,
+        git_config: &[(String, String)],
+    ) -> Result<BuildLog> {
+        if !veryl_root.join("Veryl.toml").exists() {
+            return Ok(BuildLog {
+                rev: String::new(),
+                veryl_version: ctx.version.clone(),
+                result: BuildResult::NoVerylToml,
+                stdout: String::new(),
+                stderr: String::new(),
+                usage: None,
+                steps: vec![],
+                lint: None,
+            });
+        }
+
+        let mut steps = vec![];
+        let mut lint = None;
+        for step in &ctx.steps {
+            let log = Self::run_step(ctx, veryl_root, git_config, *step).await?;
+            if *step == Step::Build && log.result.is_success() {
+                lint = Self::lint_project(ctx, veryl_root).await?;
+            }
+            steps.push(log);
+        }
+
+        // The first failed step represents the whole build
+        let main = steps
+            .iter()
+            .find(|x| !x.result.is_success())
+            .or(steps.last())
+            .unwrap();
+        let result = main.result.clone();
+        let stdout = main.stdout.clone();
+        let stderr = main.stderr.clone();
+        let usage = steps
+            .iter()
+            .find(|x| x.step == Step::Build)
+            .and_then(|x| x.usage);
+
+        // Only `veryl build` is run by default, and it is represented by the result itself
+        if ctx.steps == [Step::Build] {
+            steps.clear();
+        }
+
+        Ok(BuildLog {
+            rev: String::new(),
+            veryl_version: ctx.version.clone(),
+            result,
+            stdout,
+            stderr,
+            usage,
+            steps,
+            lint,
+        })
+    }
+
+    async fn run_step(
+        ctx: &BuildContext,
+        veryl_root: &Path,
+        git_config: &[(String, String)],
+        step: Step,
+    ) -> Result<StepLog> {
+        let run = Self::run_veryl(ctx, veryl_root, git_config, step.args()).await?;
+        Ok(StepLog {
+            step,
+            result: run.result,
+            stdout: run.stdout,
+            stderr: run.stderr,
+            usage: run.usage,
+        })
+    }
+
+    /// Run veryl with the limits, and return the result and output excerpts of a failed run
+    async fn run_veryl(
+        ctx: &BuildContext,
+        veryl_root: &Path,
+        git_config: &[(String, String)],
+        args: &[&str],
+    ) -> Result<VerylRun> {
+        let mut cmd = std::process::Command::new(&ctx.veryl);
+        if let Some(x) = &ctx.version_arg {
+            cmd.arg(x);
+        }
+        cmd.args(args)
+            .current_dir(veryl_root)
+            .env("XDG_CACHE_HOME", ctx.dir.join(VERYL_CACHE_DIR))
+            .env("GIT_TERMINAL_PROMPT", "0")
+            .env("GIT_CONFIG_COUNT", git_config.len().to_string());
+        for (i, (key, value)) in git_config.iter().enumerate() {
+            cmd.env(format!("GIT_CONFIG_KEY_{i}"), key);
+            cmd.env(format!("GIT_CONFIG_VALUE_{i}"), value);
+        }
+        ctx.limits.apply(&mut cmd);
+
+        match usage::output(cmd, ctx.limits.timeout).await? {
+            Some((output, usage)) => {
+                let result = ctx.limits.classify(&output);
+                // Output of successful runs is not kept so that the DB stays small
+                let (stdout, stderr) = if result.is_success() {
+                    (String::new(), String::new())
+                } else {
+                    (excerpt(&output.stdout), excerpt(&output.stderr))
+                };
+                Ok(VerylRun {
+                    result,
+                    stdout,
+                    stderr,
+                    usage: Some(usage),
+                })
+            }
+            None => Ok(VerylRun {
+                result: BuildResult::Timeout {
+                    seconds: ctx.limits.timeout.as_secs(),
+                },
+                stdout: String::new(),
+                stderr: format!("killed after {} seconds", ctx.limits.timeout.as_secs()),
+                usage: None,
+            }),
+        }
+    }
+
+    /// Run the formatter twice, and check that the second pass changes nothing and the formatted code still builds
+    async fn fmt_project(
+        ctx: &BuildContext,
+        veryl_root: &Path,
+        git_config: &[(String, String)],
+    ) -> Result<FmtCheck> {
+        let original = veryl_sources(veryl_root)?;
+
+        let run = Self::run_veryl(ctx, veryl_root, git_config, &["fmt"]).await?;
+        if !run.result.is_success() {
+            return Ok(FmtCheck::FormatFailed {
+                result: run.result,
+                stderr: run.stderr,
+            });
+        }
+        let first = veryl_sources(veryl_root)?;
+
+        let run = Self::run_veryl(ctx, veryl_root, git_config, &["fmt"]).await?;
+        if !run.result.is_success() {
+            return Ok(FmtCheck::FormatFailed {
+                result: run.result,
+                stderr: run.stderr,
+            });
+        }
+        let second = veryl_sources(veryl_root)?;
+
+        let files = changed_files(&first, &second);
+        if !files.is_empty() {
+            return Ok(FmtCheck::NotIdempotent { files });
+        }
+
+        let run = Self::run_veryl(ctx, veryl_root, git_config, &["build"]).await?;
+        if !run.result.is_success() {
+            // Compile errors can't be mapped to files, so files changed by the formatter are reported
+            let files = changed_files(&original, &first);
+            return Ok(FmtCheck::BuildFailed {
+                result: run.result,
+                files,
+                stderr: run.stderr,
+            });
+        }
+
+        Ok(FmtCheck::Success)
+    }
+
+    /// Check generated SystemVerilog by the tool if specified
+    async fn lint_project(ctx: &BuildContext, veryl_root: &Path) -> Result<Option<LintLog>> {
+        let Some((tool, path)) = &ctx.lint else {
+            return Ok(None);
+        };
+        let files = lint::sources(veryl_root);
+        if files.is_empty() {
+            return Ok(None);
+        }
+
+        let mut cmd = tool.command(path, &files);
+        cmd.current_dir(veryl_root).kill_on_drop(true);
+        ctx.limits.apply(cmd.as_std_mut());
+
+        let (result, stderr) = match time::timeout(ctx.limits.timeout, cmd.output()).await {
+            Ok(output) => {
+                let output = output?;
+                // Some tools report errors to stdout
+                let mut text = output.stdout;
+                text.extend(output.stderr);
+                let result = if output.status.success() {
+                    LintResult::Success
+                } else {
+                    LintResult::Error {
+                        exit_code: output.status.code(),
+                    }
+                };
+                (result, excerpt(&text))
+            }
+            Err(_) => {
+                let seconds = ctx.limits.timeout.as_secs();
+                (LintResult::Timeout { seconds }, String::new())
+            }
+        };
+
+        Ok(Some(LintLog {
+            tool: *tool,
+            result,
+            stderr,
+        }))
+    }
+}
+
+/// Result of the formatter check of a project
+#[derive(Debug, PartialEq, Eq)]
+enum FmtCheck {
+    Success,
+    /// Clone or dependency fetch failed, so the formatter was not checked
+    Unavailable {
+        message: String,
+    },
+    FormatFailed {
+        result: BuildResult,
+        stderr: String,
+    },
+    /// Files changed by the second pass
+    NotIdempotent {
+        files: Vec<String>,
+    },
+    /// Files changed by the formatter which made the build fail
+    BuildFailed {
+        result: BuildResult,
+        files: Vec<String>,
+        stderr: String,
+    },
+}
+
+impl fmt::Display for FmtCheck {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            FmtCheck::Success => "Success".fmt(f),
+            FmtCheck::Unavailable { .. } => "Unavailable".fmt(f),
+            FmtCheck::FormatFailed { result, .. } => write!(f, "FormatFailed ({result})"),
+            FmtCheck::NotIdempotent { .. } => "NotIdempotent".fmt(f),
+            FmtCheck::BuildFailed { result, .. } => write!(f, "BuildFailed ({result})"),
+        }
+    }
+}
+
+/// Contents of Veryl sources under the project by relative path
+fn veryl_sources(veryl_root: &Path) -> Result<BTreeMap<String, Vec<u8>>> {
+    let mut ret = BTreeMap::new();
+    for entry in WalkDir::new(veryl_root)
+        .into_iter()
+        .filter_entry(|x| x.file_name() != ".git")
+    {
+        let entry = entry?;
+        if entry.file_type().is_file() && entry.path().extension().is_some_and(|x| x == "veryl") {
+            let rel = entry.path().strip_prefix(veryl_root)?;
+            let rel: Vec<_> = rel.iter().map(|x| x.to_string_lossy()).collect();
+            ret.insert(rel.join("/"), fs::read(entry.path())?);
+        }
+    }
+    Ok(ret)
+}
+
+fn changed_files(old: &BTreeMap<String, Vec<u8>>, new: &BTreeMap<String, Vec<u8>>) -> Vec<String> {
+    new.iter()
+        .filter(|(path, text)| old.get(*path) != Some(*text))
+        .map(|(path, _)| path.clone())
+        .collect()
+}
+
+/// Failed git command
+struct GitError {
+    output: Output,
+    /// Network errors continued after all retries
+    exhausted: bool,
+}
+
+impl GitError {
+    /// The fatal line of the output, or the last line if not found
+    fn reason(&self) -> String {
+        let stderr = String::from_utf8_lossy(&self.output.stderr);
+        stderr
+            .lines()
+            .find(|x| x.starts_with("fatal:"))
+            .or(stderr.lines().last())
+            .unwrap_or_default()
+            .trim()
+            .to_string()
+    }
+}
+
+#[derive(Default)]
+struct RepoBuild {
+    /// Paths of Veryl projects found in the checkout
+    roots: Option<Vec<String>>,
+    build_logs: Vec<(String, BuildLog)>,
+    dependencies: Vec<(String, Vec<Url>)>,
+    skipped: Vec<SkipReason>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum SkipReason {
+    Unchanged,
+    PreviousFailure,
+}
+
+impl fmt::Display for SkipReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            SkipReason::Unchanged => "revision and Veryl version are unchanged",
+            SkipReason::PreviousFailure => "failed previously (use --all to build)",
+        };
+        text.fmt(f)
+    }
+}
+
+#[derive(Default)]
+struct BuildSummary {
+    built: usize,
+    infrastructure_failures: usize,
+    skipped: BTreeMap<SkipReason, usize>,
+}
+
+impl BuildSummary {
+    fn skipped(&mut self, reason: SkipReason, count: usize) {
+        *self.skipped.entry(reason).or_default() += count;
+    }
+
+    fn print(&self) {
+        let skipped: usize = self.skipped.values().sum();
+        println!("Built {} projects, skipped {skipped} projects", self.built);
+        for (reason, count) in &self.skipped {
+            println!("  {count} projects: {reason}");
+        }
+        if self.infrastructure_failures != 0 {
+            println!(
+                "{} projects failed by infrastructure problems",
+                self.infrastructure_failures
+            );
+        }
+    }
+}
+
+/// Revisions of successfully checked out projects
+fn revs_of(logs: &HashMap<u64, BuildLog>) -> HashMap<u64, String> {
+    logs.iter()
+        .filter(|(_, x)| !x.rev.is_empty())
+        .map(|(id, x)| (*id, x.rev.clone()))
+        .collect()
+}
+
+/// Relative path of the repository in the cache like "github.com/owner/repo".
+/// The host is included because dependencies may be hosted anywhere.
+fn repo_path(url: &Url) -> String {
+    let host = url.host_str().unwrap_or(url.scheme());
+    format!("{host}/{}", url.path().trim_start_matches('/'))
+}
+
+/// git command which fails instead of prompting for credentials
+fn git() -> Command {
+    let mut cmd = Command::new("git");
+    cmd.env("GIT_TERMINAL_PROMPT", "0").stdin(Stdio::null());
+    cmd
+}
+
+/// Run the git command accessing a remote repository, and report the timeout as a network error
+async fn git_remote(cmd: &mut Command) -> Result<Output> {
+    cmd.kill_on_drop(true);
+    match time::timeout(GIT_REMOTE_TIMEOUT, cmd.output()).await {
+        Ok(output) => Ok(output?),
+        Err(_) => Ok(Output {
+            status: ExitStatus::from_raw(libc::SIGKILL),
+            stdout: vec![],
+            stderr: format!(
+                "git timed out after {} seconds\n",
+                GIT_REMOTE_TIMEOUT.as_secs()
+            )
+            .into_bytes(),
+        }),
+    }
+}
+
+/// The specified compiler, or `veryl` in PATH
+fn local_veryl(path: Option<&PathBuf>) -> Result<PathBuf> {
+    if let Some(path) = path {
+        Ok(path.canonicalize()?)
+    } else {
+        Ok(which::which("veryl")?)
+    }
+}
+
+/// A compiler is specified by a release version installed through verylup, or a path to the binary
+fn compiler_source(source: &str) -> Result<(PathBuf, Option<String>)> {
+    if let Ok(version) = Version::parse(source) {
+        Ok((which::which("veryl")?, Some(format!("+{version}"))))
+    } else {
+        Ok((PathBuf::from(source).canonicalize()?, None))
+    }
+}
+
+/// Clone cache is kept across runs, but worktrees are always checked out from scratch
+fn prepare_build_dir(dir: &Path) -> Result<()> {
+    let work_dir = dir.join(WORK_DIR);
+    if work_dir.exists() {
+        fs::remove_dir_all(&work_dir)?;
+    }
+    fs::create_dir_all(dir.join(CACHE_DIR))?;
+    fs::create_dir_all(&work_dir)?;
+    Ok(())
+}
+
+struct BuildContext {
+    dir: PathBuf,
+    veryl: PathBuf,
+    version: Version,
+    version_arg: Option<String>,
+    update_db: bool,
+    limits: BuildLimits,
+    steps: Vec<Step>,
+    /// Tool and its path to check generated code
+    lint: Option<(LintTool, PathBuf)>,
+    cache_locks: StdMutex<HashMap<PathBuf, Arc<Mutex<()>>>>,
+}
+
+impl BuildContext {
+    async fn new(
+        dir: &Path,
+        veryl: PathBuf,
+        version_arg: Option<String>,
+        update_db: bool,
+        limits: BuildLimits,
+    ) -> Result<BuildContext> {
+        let mut version = Command::new(&veryl);
+        if let Some(x) = &version_arg {
+            version.arg(x);
+        }
+        let version = version.arg("--version").output().await?;
+        let version = String::from_utf8(version.stdout)?;
+        let version = version.replace("veryl ", "").trim().to_string();
+        let version = Version::parse(&version)?;
+
+        Ok(BuildContext {
+            dir: dir.canonicalize()?,
+            veryl,
+            version,
+            version_arg,
+            update_db,
+            limits,
+            steps: vec![Step::Build],
+            lint: None,
+            cache_locks: StdMutex::new(HashMap::new()),
+        })
+    }
+
+    fn work_path(&self, url: &Url) -> PathBuf {
+        self.dir.join(WORK_DIR).join(repo_path(url))
+    }
+
+    fn cache_path(&self, url: &Url) -> PathBuf {
+        self.dir
+            .join(CACHE_DIR)
+            .join(format!("{}.git", repo_path(url)))
+    }
+
+    /// A clone cache may be shared by a project and dependencies of other projects
+    async fn lock_cache(&self, cache: &Path) -> OwnedMutexGuard<()> {
+        let lock = self
+            .cache_locks
+            .lock()
+            .unwrap()
+            .entry(cache.to_path_buf())
+            .or_default()
+            .clone();
+        lock.lock_owned().await
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Discovered {
+    #[serde(with = "ts_seconds")]
+    pub date: DateTime<Utc>,
+    pub sources: u64,
+    pub projects: Vec<u64>,
+    pub pages: u64,
+    pub items: u64,
+}
+
+#[derive(Default)]
+struct SearchResult {
+    pages: u64,
+    items: Vec<Code>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Download {
+    #[serde(with = "ts_seconds")]
+    pub date: DateTime<Utc>,
+    pub counts: HashMap<Platform, u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Hash, Clone)]
+pub enum Platform {
+    Aarch64Mac,
+    X86_64Linux,
+    X86_64Mac,
+    X86_64Windows,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct GithubRelease {
-    name: String,
-    assets: Vec<GithubReleaseAsset>,
+    pub name: String,
+    pub assets: Vec<GithubReleaseAsset>,
 }
 
 #[derive(Deserialize, Debug)]
 pub struct GithubReleaseAsset {
-    name: String,
-    download_count: u64,
+    pub name: String,
+    pub download_count: u64,
+    pub browser_download_url: String,
+    /// Checksum like "sha256:..." computed by GitHub
+    #[serde(default)]
+    pub digest: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn output(status: i32, stderr: &str) -> Output {
+        Output {
+            status: ExitStatus::from_raw(status),
+            stdout: vec![],
+            stderr: stderr.as_bytes().to_vec(),
+        }
+    }
+
+    fn limits() -> BuildLimits {
+        BuildLimits {
+            timeout: DEFAULT_BUILD_TIMEOUT,
+            memory: Some(1 << 30),
+            cpu: Some(1),
+        }
+    }
+
+    #[test]
+    fn classify_signal() {
+        let limits = limits();
+        assert_eq!(
+            limits.classify(&output(libc::SIGXCPU, "")),
+            BuildResult::Timeout { seconds: 1 }
+        );
+        // SIGKILL is not caused by the memory limit
+        assert_eq!(
+            limits.classify(&output(libc::SIGKILL, "")),
+            BuildResult::CompileError { exit_code: None }
+        );
+        assert_eq!(
+            limits.classify(&output(
+                libc::SIGABRT,
+                "memory allocation of 4096 bytes failed\n"
+            )),
+            BuildResult::OutOfMemory {
+                limit: Some(1 << 30)
+            }
+        );
+    }
+
+    #[test]
+    fn classify_stderr() {
+        let limits = BuildLimits::default();
+        assert_eq!(limits.classify(&output(0, "")), BuildResult::Success);
+        assert_eq!(
+            limits.classify(&output(
+                101 << 8,
+                "thread 'main' panicked at src/a.rs:1:2:\nindex out of bounds\n"
+            )),
+            BuildResult::CompilerPanic {
+                message: "thread 'main' panicked at src/a.rs:1:2: index out of bounds".to_string()
+            }
+        );
+        assert_eq!(
+            limits.classify(&output(1 << 8, "Error: failed to clone dependency\n")),
+            BuildResult::DependencyFetchFailed {
+                message: "Error: failed to clone dependency".to_string()
+            }
+        );
+        assert_eq!(
+            limits.classify(&output(1 << 8, "Error: unknown identifier\n")),
+            BuildResult::CompileError { exit_code: Some(1) }
+        );
+    }
+
+    fn build_log(rev: &str) -> BuildLog {
+        BuildLog {
+            rev: rev.to_string(),
+            veryl_version: Version::new(0, 13, 0),
+            result: BuildResult::Success,
+            stdout: String::new(),
+            stderr: String::new(),
+            usage: None,
+            steps: vec![],
+            lint: None,
+        }
+    }
+
+    #[test]
+    fn retire_root_merges_history() {
+        let url = Url::parse("https://github.com/veryl-lang/mono").unwrap();
+        let mut db = Db::new();
+        let root = db.insert_project(Project {
+            url: url.clone(),
+            path: String::new(),
+            build_logs: vec![build_log("a"), build_log("b")],
+            dependencies: vec![],
+            bisect_logs: vec![build_log("x")],
+        });
+        let hw = db.insert_project(Project {
+            url: url.clone(),
+            path: "hw".to_string(),
+            build_logs: vec![build_log("c")],
+            dependencies: vec![],
+            bisect_logs: vec![],
+        });
+        db.push_discovered(Discovered {
+            date: Utc::now(),
+            sources: 0,
+            projects: vec![root, hw],
+            pages: 0,
+            items: 0,
+        });
+
+        db.retire_root(&url, &["hw".to_string()]);
+
+        assert!(!db.projects.contains_key(&root));
+        let revs: Vec<_> = db.projects[&hw]
+            .build_logs
+            .iter()
+            .map(|x| x.rev.as_str())
+            .collect();
+        assert_eq!(revs, ["a", "b", "c"]);
+        assert_eq!(db.projects[&hw].bisect_logs.len(), 1);
+        assert_eq!(db.discovered[0].projects, [hw]);
+        assert!(db.validate().is_empty());
+    }
+
+    #[test]
+    fn retire_root_moves_project() {
+        let url = Url::parse("https://github.com/veryl-lang/mono").unwrap();
+        let mut db = Db::new();
+        let root = db.insert_project(Project {
+            url: url.clone(),
+            path: String::new(),
+            build_logs: vec![build_log("a")],
+            dependencies: vec![],
+            bisect_logs: vec![],
+        });
+
+        db.retire_root(&url, &["hw".to_string(), "sim".to_string()]);
+
+        assert_eq!(db.projects[&root].path, "hw");
+        assert_eq!(db.projects[&root].build_logs.len(), 1);
+    }
+
+    #[test]
+    fn cpu_limit_is_timeout() {
+        let limits = limits();
+        let mut cmd = std::process::Command::new("sh");
+        cmd.arg("-c").arg("while :; do :; done");
+        limits.apply(&mut cmd);
+        let output = cmd.output().unwrap();
+        assert_eq!(
+            limits.classify(&output),
+            BuildResult::Timeout { seconds: 1 }
+        );
+    }
 }
@@ -0,0 +1,49 @@
+use anyhow::{anyhow, Result};
+use std::path::Path;
+use toml::Value;
+use url::Url;
+
+/// Collect git URLs of dependencies declared in `[dependencies]` of Veryl.toml.
+///
+/// Both forms are supported:
+///
+/// ```toml
+/// [dependencies]
+/// "https://github.com/veryl-lang/sample" = "0.1.0"
+/// sample = { github = "veryl-lang/sample", version = "0.1.0" }
+/// ```
+pub fn dependencies(veryl_toml: &str) -> Result<Vec<Url>> {
+    let value: Value = toml::from_str(veryl_toml)?;
+
+    let Some(deps) = value.get("dependencies").and_then(|x| x.as_table()) else {
+        return Ok(vec![]);
+    };
+
+    let mut ret = vec![];
+    for (key, value) in deps {
+        let url = if let Ok(url) = Url::parse(key) {
+            url
+        } else if let Some(x) = value.get("github").and_then(|x| x.as_str()) {
+            Url::parse(&format!("https://github.com/{x}"))?
+        } else if let Some(x) = value.get("git").and_then(|x| x.as_str()) {
+            Url::parse(x)?
+        } else {
+            return Err(anyhow!("unknown source of dependency \"{key}\""));
+        };
+        ret.push(normalize(url));
+    }
+
+    Ok(ret)
+}
+
+pub fn dependencies_in<T: AsRef<Path>>(veryl_root: T) -> Result<Vec<Url>> {
+    let text = std::fs::read_to_string(veryl_root.as_ref().join("Veryl.toml"))?;
+    dependencies(&text)
+}
+
+/// Remove ".git" suffix and trailing slash so that the same repository has the same URL
+pub fn normalize(url: Url) -> Url {
+    let text = url.as_str().trim_end_matches('/');
+    let text = text.strip_suffix(".git").unwrap_or(text);
+    Url::parse(text).unwrap_or(url)
+}
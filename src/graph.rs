@@ -0,0 +1,146 @@
+use crate::db::{BuildResult, Db, Project};
+use crate::dependency;
+use anyhow::Result;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// Dependency graph between projects.
+/// Dependencies which are not discovered projects are kept as external nodes.
+#[derive(Serialize)]
+pub struct Graph {
+    nodes: Vec<Node>,
+    edges: Vec<Edge>,
+}
+
+#[derive(Serialize)]
+struct Node {
+    id: usize,
+    name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    project: Option<u64>,
+}
+
+/// `from` depends on `to`
+#[derive(Serialize, PartialEq, Eq, PartialOrd, Ord)]
+struct Edge {
+    from: usize,
+    to: usize,
+}
+
+fn no_veryl_toml(prj: &Project) -> bool {
+    prj.build_logs
+        .last()
+        .is_some_and(|x| x.result == BuildResult::NoVerylToml)
+}
+
+impl Graph {
+    pub fn new(db: &Db) -> Graph {
+        let mut ids: Vec<_> = db.projects.keys().copied().collect();
+        ids.sort();
+
+        let mut nodes = vec![];
+        let mut node_of_project = BTreeMap::new();
+        let mut projects_of_repo: BTreeMap<_, Vec<_>> = BTreeMap::new();
+        for id in &ids {
+            let prj = &db.projects[id];
+            node_of_project.insert(*id, nodes.len());
+            projects_of_repo
+                .entry(dependency::normalize(prj.url.clone()))
+                .or_default()
+                .push(*id);
+            nodes.push(Node {
+                id: nodes.len(),
+                name: prj.to_string(),
+                project: Some(*id),
+            });
+        }
+
+        // A dependency refers to the repository root, or its sub-projects if the root has no Veryl.toml
+        let mut nodes_of_repo: BTreeMap<_, Vec<_>> = projects_of_repo
+            .into_iter()
+            .map(|(url, ids)| {
+                let root = ids.iter().find(|x| db.projects[x].path.is_empty());
+                let targets: Vec<_> = match root {
+                    Some(root) if ids.len() == 1 || !no_veryl_toml(&db.projects[root]) => {
+                        vec![*root]
+                    }
+                    _ => ids
+                        .into_iter()
+                        .filter(|x| !db.projects[x].path.is_empty())
+                        .collect(),
+                };
+                (url, targets.iter().map(|x| node_of_project[x]).collect())
+            })
+            .collect();
+
+        let mut edges = BTreeSet::new();
+        for id in &ids {
+            let from = node_of_project[id];
+            for dep in &db.projects[id].dependencies {
+                let dep = dependency::normalize(dep.clone());
+                let targets = nodes_of_repo.entry(dep.clone()).or_insert_with(|| {
+                    nodes.push(Node {
+                        id: nodes.len(),
+                        name: dep.to_string(),
+                        project: None,
+                    });
+                    vec![nodes.len() - 1]
+                });
+                for to in targets.iter() {
+                    edges.insert(Edge { from, to: *to });
+                }
+            }
+        }
+
+        Graph {
+            nodes,
+            edges: edges.into_iter().collect(),
+        }
+    }
+
+    /// Projects which depend on the project directly or transitively
+    pub fn dependents(&self, project: u64) -> Vec<u64> {
+        let Some(start) = self.nodes.iter().find(|x| x.project == Some(project)) else {
+            return vec![];
+        };
+
+        let mut visited = BTreeSet::from([start.id]);
+        let mut queue = vec![start.id];
+        while let Some(node) = queue.pop() {
+            for edge in self.edges.iter().filter(|x| x.to == node) {
+                if visited.insert(edge.from) {
+                    queue.push(edge.from);
+                }
+            }
+        }
+        visited.remove(&start.id);
+
+        visited
+            .into_iter()
+            .filter_map(|x| self.nodes[x].project)
+            .collect()
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut ret = String::new();
+        let _ = writeln!(ret, "digraph dependencies {{");
+        for node in &self.nodes {
+            let style = if node.project.is_some() {
+                ""
+            } else {
+                ", style=dashed"
+            };
+            let _ = writeln!(ret, "    n{} [label=\"{}\"{style}];", node.id, node.name);
+        }
+        for edge in &self.edges {
+            let _ = writeln!(ret, "    n{} -> n{};", edge.from, edge.to);
+        }
+        let _ = writeln!(ret, "}}");
+        ret
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
@@ -0,0 +1,142 @@
+use clap::ValueEnum;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use tokio::process::Command;
+use walkdir::WalkDir;
+
+/// SystemVerilog tools to check generated code after `veryl build`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+pub enum LintTool {
+    Verilator,
+    Iverilog,
+    Slang,
+}
+
+impl LintTool {
+    pub fn binary(&self) -> &'static str {
+        match self {
+            LintTool::Verilator => "verilator",
+            LintTool::Iverilog => "iverilog",
+            LintTool::Slang => "slang",
+        }
+    }
+
+    /// Build the command to check the files without generating anything
+    pub fn command(&self, path: &Path, files: &[PathBuf]) -> Command {
+        let mut cmd = Command::new(path);
+        match self {
+            LintTool::Verilator => cmd.arg("--lint-only").arg("-Wno-fatal"),
+            LintTool::Iverilog => cmd.arg("-g2012").arg("-o").arg("/dev/null"),
+            LintTool::Slang => cmd.arg("--lint-only"),
+        };
+        cmd.args(files);
+        cmd
+    }
+}
+
+impl fmt::Display for LintTool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.binary().fmt(f)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LintLog {
+    pub tool: LintTool,
+    pub result: LintResult,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub stderr: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub enum LintResult {
+    Success,
+    Error { exit_code: Option<i32> },
+    Timeout { seconds: u64 },
+}
+
+impl LintResult {
+    pub fn is_success(&self) -> bool {
+        *self == LintResult::Success
+    }
+}
+
+impl fmt::Display for LintResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let text = match self {
+            LintResult::Success => "Success",
+            LintResult::Error { .. } => "Error",
+            LintResult::Timeout { .. } => "Timeout",
+        };
+        text.fmt(f)
+    }
+}
+
+/// SystemVerilog files generated by `veryl build` in compilation order.
+/// The filelist written by Veryl is used if found, because it lists packages before their users.
+pub fn sources(veryl_root: &Path) -> Vec<PathBuf> {
+    if let Some(files) = filelist(veryl_root) {
+        return files;
+    }
+
+    // Only files generated from Veryl sources are checked, and packages are placed first
+    let mut stems = HashSet::new();
+    let mut outputs = vec![];
+    for entry in WalkDir::new(veryl_root)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|x| x.file_name() != ".git")
+        .filter_map(|x| x.ok())
+        .filter(|x| x.file_type().is_file())
+    {
+        let path = entry.into_path();
+        let Some(stem) = path.file_stem().map(|x| x.to_os_string()) else {
+            continue;
+        };
+        match path.extension().and_then(|x| x.to_str()) {
+            Some("veryl") => {
+                stems.insert(stem);
+            }
+            Some("sv") => outputs.push((stem, path)),
+            _ => (),
+        }
+    }
+
+    let mut files: Vec<_> = outputs
+        .into_iter()
+        .filter(|(stem, _)| stems.contains(stem))
+        .map(|(_, path)| (!is_package(&path), path))
+        .collect();
+    files.sort();
+    files.into_iter().map(|(_, path)| path).collect()
+}
+
+/// Read `<project name>.f` generated by Veryl
+fn filelist(veryl_root: &Path) -> Option<Vec<PathBuf>> {
+    let text = fs::read_to_string(veryl_root.join("Veryl.toml")).ok()?;
+    let value: toml::Value = toml::from_str(&text).ok()?;
+    let name = value.get("project")?.get("name")?.as_str()?;
+    let text = fs::read_to_string(veryl_root.join(format!("{name}.f"))).ok()?;
+
+    // Comments and options are skipped, and `source_file 'x.sv'` of the flgen format is supported
+    let files: Vec<_> = text
+        .lines()
+        .map(|x| x.trim())
+        .filter(|x| !x.starts_with("//") && !x.starts_with(['#', '+', '-']))
+        .filter_map(|x| x.split_whitespace().last())
+        .map(|x| x.trim_matches(['\'', '"']))
+        .filter(|x| x.ends_with(".sv"))
+        .map(|x| veryl_root.join(x))
+        .filter(|x| x.exists())
+        .collect();
+    (!files.is_empty()).then_some(files)
+}
+
+fn is_package(path: &Path) -> bool {
+    fs::read_to_string(path)
+        .is_ok_and(|x| x.lines().any(|x| x.trim_start().starts_with("package ")))
+}
@@ -1,14 +1,29 @@
 mod db;
+mod dependency;
+mod graph;
+mod lint;
+mod migration;
+mod output;
+mod storage;
+mod toolchain;
+mod usage;
 
-use crate::db::Db;
-use anyhow::Result;
-use clap::{Args, Parser, Subcommand};
+use crate::db::{BuildLimits, Db, Step};
+use crate::graph::Graph;
+use crate::lint::LintTool;
+use crate::storage::Lock;
+use crate::toolchain::Release;
+use anyhow::{anyhow, Result};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use semver::Version;
 use std::path::PathBuf;
+use std::time::Duration;
 
 const DB_DIR: &str = "db";
 const BUILD_DIR: &str = "build";
 const JSON_PATH: &str = "db/db.json";
 const SVG_PATH: &str = "db/plot.svg";
+const LOCK_PATH: &str = "db/db.lock";
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -30,11 +45,47 @@ struct Opt {
 enum Commands {
     Update(OptUpdate),
     Check(OptCheck),
+    Log(OptLog),
+    Validate(OptValidate),
+    Migrate(OptMigrate),
+    Import(OptImport),
+    Export(OptExport),
+    Gc(OptGc),
+    Graph(OptGraph),
+    Bisect(OptBisect),
+    BisectCommit(OptBisectCommit),
+    Compare(OptCompare),
+    Fmt(OptFmt),
+    Perf(OptPerf),
+}
+
+impl Commands {
+    /// Commands which write the DB or use the build directory
+    fn needs_lock(&self) -> bool {
+        !matches!(
+            self,
+            Commands::Log(_)
+                | Commands::Validate(_)
+                | Commands::Export(_)
+                | Commands::Graph(_)
+                | Commands::Perf(_)
+        )
+    }
 }
 
 /// Update DB
 #[derive(Args)]
-pub struct OptUpdate;
+pub struct OptUpdate {
+    /// Number of parallel build jobs [default: number of CPUs]
+    #[arg(long, short)]
+    jobs: Option<usize>,
+    /// Veryl release to build with [default: latest]
+    #[arg(long)]
+    release: Option<Version>,
+    /// Expected SHA-256 of the release archive [default: published checksum]
+    #[arg(long, requires = "release")]
+    sha256: Option<String>,
+}
 
 /// Check
 #[derive(Args)]
@@ -43,12 +94,205 @@ pub struct OptCheck {
     path: Option<PathBuf>,
     #[arg(long)]
     veryl_version: Option<String>,
+    /// Download the Veryl release instead of local veryl
+    #[arg(long, conflicts_with_all = ["path", "veryl_version"])]
+    release: Option<Version>,
+    /// Expected SHA-256 of the release archive [default: published checksum]
+    #[arg(long, requires = "release")]
+    sha256: Option<String>,
     #[arg(long)]
     all: bool,
+    /// Number of parallel build jobs [default: number of CPUs]
+    #[arg(long, short)]
+    jobs: Option<usize>,
+    #[command(flatten)]
+    limits: OptLimits,
+    /// Print build log of failed projects
+    #[arg(long)]
+    log: bool,
+    /// Check generated SystemVerilog by the tool after build
+    #[arg(long, value_enum)]
+    lint: Option<LintTool>,
+    /// Steps of veryl run for each project [default: build]
+    #[arg(long, value_enum, value_delimiter = ',')]
+    steps: Option<Vec<Step>>,
+    /// Build projects N times, and report projects whose results or outputs differ
+    #[arg(long, default_value_t = 1, value_parser = clap::value_parser!(u32).range(1..))]
+    repeat: u32,
+}
+
+impl OptCheck {
+    fn to_release(&self) -> Option<Release> {
+        self.release.clone().map(|version| Release {
+            version: Some(version),
+            sha256: self.sha256.clone(),
+        })
+    }
+}
+
+/// Show build log
+#[derive(Args)]
+pub struct OptLog {
+    /// Project ID, URL or repository name like "owner/repo"
+    project: String,
+    /// Index of build run [default: latest]
+    #[arg(long)]
+    run: Option<usize>,
+}
+
+#[derive(Args)]
+pub struct OptLimits {
+    /// Timeout of each build in seconds
+    #[arg(long, default_value_t = 600)]
+    timeout: u64,
+    /// Memory limit of each build in MiB
+    #[arg(long)]
+    memory_limit: Option<u64>,
+    /// CPU time limit of each build in seconds
+    #[arg(long)]
+    cpu_limit: Option<u64>,
+}
+
+impl OptLimits {
+    fn to_limits(&self) -> BuildLimits {
+        BuildLimits {
+            timeout: Duration::from_secs(self.timeout),
+            memory: self.memory_limit.map(|x| x * 1024 * 1024),
+            cpu: self.cpu_limit,
+        }
+    }
+}
+
+/// Validate DB
+#[derive(Args)]
+pub struct OptValidate;
+
+/// Migrate DB to the latest schema
+#[derive(Args)]
+pub struct OptMigrate;
+
+/// Import DB from JSON or SQLite file
+#[derive(Args)]
+pub struct OptImport {
+    /// Source file (.json, .sqlite)
+    path: PathBuf,
+}
+
+/// Export DB to JSON or SQLite file
+#[derive(Args)]
+pub struct OptExport {
+    /// Destination file (.json, .sqlite)
+    path: PathBuf,
+}
+
+/// Remove clone caches of projects which no longer exist
+#[derive(Args)]
+pub struct OptGc;
+
+/// Print dependency graph between projects
+#[derive(Args)]
+pub struct OptGraph {
+    #[arg(long, value_enum, default_value_t = GraphFormat::Dot)]
+    format: GraphFormat,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum GraphFormat {
+    Dot,
+    Json,
+}
+
+/// Find the first Veryl release which breaks a project
+#[derive(Args)]
+pub struct OptBisect {
+    /// Project ID, URL or repository name like "owner/repo"
+    project: String,
+    /// Version which builds the project successfully
+    #[arg(long)]
+    good: Version,
+    /// Version which fails to build the project
+    #[arg(long)]
+    bad: Version,
+    #[command(flatten)]
+    limits: OptLimits,
+}
+
+/// Find the first commit of local Veryl repository which breaks a project
+#[derive(Args)]
+pub struct OptBisectCommit {
+    /// Project ID, URL or repository name like "owner/repo"
+    project: String,
+    /// Path to local Veryl repository
+    #[arg(long)]
+    repo: PathBuf,
+    /// Commit which builds the project successfully
+    #[arg(long)]
+    good: String,
+    /// Commit which fails to build the project
+    #[arg(long)]
+    bad: String,
+    #[command(flatten)]
+    limits: OptLimits,
+}
+
+/// Compare build results of all projects between two compilers
+#[derive(Args)]
+pub struct OptCompare {
+    /// Baseline compiler: release version or path to veryl binary
+    base: String,
+    /// Compiler to be compared: release version or path to veryl binary
+    target: String,
+    /// Number of parallel build jobs [default: number of CPUs]
+    #[arg(long, short)]
+    jobs: Option<usize>,
+    #[command(flatten)]
+    limits: OptLimits,
+    /// Print build log of newly failing projects
+    #[arg(long)]
+    log: bool,
+    /// Keep generated SystemVerilog and compare it ignoring whitespace and comments
+    #[arg(long)]
+    outputs: bool,
+    /// Print unified diffs of generated SystemVerilog
+    #[arg(long, requires = "outputs")]
+    diff: bool,
+}
+
+/// Check that the formatter is idempotent and keeps projects buildable
+#[derive(Args)]
+pub struct OptFmt {
+    #[arg(long)]
+    path: Option<PathBuf>,
+    #[arg(long)]
+    veryl_version: Option<String>,
+    /// Number of parallel build jobs [default: number of CPUs]
+    #[arg(long, short)]
+    jobs: Option<usize>,
+    #[command(flatten)]
+    limits: OptLimits,
+    /// Print output of failed formatter and build
+    #[arg(long)]
+    log: bool,
+}
+
+/// Report projects whose compile time or memory usage grew against the previous Veryl version
+#[derive(Args)]
+pub struct OptPerf {
+    /// Growth in percent to be reported
+    #[arg(long, default_value_t = 20.0)]
+    threshold: f64,
+}
+
+fn default_jobs() -> usize {
+    std::thread::available_parallelism()
+        .map(|x| x.get())
+        .unwrap_or(1)
 }
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let opt = Opt::parse();
+
     let dir = PathBuf::from(DB_DIR);
     let path = PathBuf::from(JSON_PATH);
 
@@ -56,23 +300,112 @@ async fn main() -> Result<()> {
         std::fs::create_dir(DB_DIR)?;
     }
 
-    let mut db = if path.exists() {
-        Db::load(&path)?
+    let _lock = if opt.command.needs_lock() {
+        Some(Lock::acquire(LOCK_PATH)?)
     } else {
-        Db::default()
+        None
     };
 
-    let opt = Opt::parse();
+    let (mut db, migrations) = if path.exists() {
+        Db::load(&path)?
+    } else {
+        (Db::new(), vec![])
+    };
 
     match opt.command {
-        Commands::Update(_) => {
+        Commands::Update(x) => {
+            let jobs = x.jobs.unwrap_or_else(default_jobs);
             db.update().await?;
-            db.build(PathBuf::from(BUILD_DIR), None).await?;
+            let release = Release {
+                version: x.release,
+                sha256: x.sha256,
+            };
+            db.build(PathBuf::from(BUILD_DIR), jobs, Some(release), None)
+                .await?;
             db.save(PathBuf::from(JSON_PATH))?;
             db.plot(PathBuf::from(SVG_PATH))?;
         }
         Commands::Check(x) => {
-            db.build(PathBuf::from(BUILD_DIR), Some(x)).await?;
+            let jobs = x.jobs.unwrap_or_else(default_jobs);
+            if x.repeat > 1 {
+                let count = db
+                    .check_determinism(PathBuf::from(BUILD_DIR), jobs, &x)
+                    .await?;
+                if count != 0 {
+                    return Err(anyhow!("{count} projects are nondeterministic"));
+                }
+            } else {
+                let release = x.to_release();
+                db.build(PathBuf::from(BUILD_DIR), jobs, release, Some(x))
+                    .await?;
+            }
+        }
+        Commands::Log(x) => {
+            db.print_log(&x)?;
+        }
+        Commands::Validate(_) => {
+            let problems = db.validate();
+            for problem in &problems {
+                println!("{problem}");
+            }
+            if !problems.is_empty() {
+                return Err(anyhow!("{} problems are found", problems.len()));
+            }
+        }
+        Commands::Migrate(_) => {
+            if migrations.is_empty() {
+                println!("DB is already schema version {}", db.schema_version);
+            } else {
+                for migration in &migrations {
+                    println!("{migration}");
+                }
+                db.save(PathBuf::from(JSON_PATH))?;
+                println!("DB is migrated to schema version {}", db.schema_version);
+            }
+        }
+        Commands::Import(x) => {
+            let (db, migrations) = Db::load(&x.path)?;
+            for migration in &migrations {
+                println!("{migration}");
+            }
+            db.save(PathBuf::from(JSON_PATH))?;
+        }
+        Commands::Export(x) => {
+            db.save(&x.path)?;
+        }
+        Commands::Gc(_) => {
+            db.gc(PathBuf::from(BUILD_DIR))?;
+        }
+        Commands::Graph(x) => {
+            let graph = Graph::new(&db);
+            match x.format {
+                GraphFormat::Dot => print!("{}", graph.to_dot()),
+                GraphFormat::Json => println!("{}", graph.to_json()?),
+            }
+        }
+        Commands::Bisect(x) => {
+            db.bisect(PathBuf::from(BUILD_DIR), &x).await?;
+            db.save(PathBuf::from(JSON_PATH))?;
+        }
+        Commands::BisectCommit(x) => {
+            db.bisect_commit(PathBuf::from(BUILD_DIR), &x).await?;
+        }
+        Commands::Compare(x) => {
+            let jobs = x.jobs.unwrap_or_else(default_jobs);
+            let failing = db.compare(PathBuf::from(BUILD_DIR), jobs, &x).await?;
+            if failing != 0 {
+                return Err(anyhow!("{failing} projects are newly failing"));
+            }
+        }
+        Commands::Fmt(x) => {
+            let jobs = x.jobs.unwrap_or_else(default_jobs);
+            let problems = db.fmt_check(PathBuf::from(BUILD_DIR), jobs, &x).await?;
+            if problems != 0 {
+                return Err(anyhow!("{problems} projects have formatter problems"));
+            }
+        }
+        Commands::Perf(x) => {
+            db.perf(&x);
         }
     }
 
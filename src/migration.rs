@@ -0,0 +1,206 @@
+use anyhow::{anyhow, Result};
+use serde_json::{json, Map, Value};
+
+pub const SCHEMA_VERSION: u64 = 3;
+
+type Migration = fn(&mut Map<String, Value>) -> Vec<String>;
+
+/// Migrations from version N to N + 1 are placed at index N
+const MIGRATIONS: &[Migration] = &[migrate_to_v1, migrate_to_v2, migrate_to_v3];
+
+/// Upgrade the database to the latest schema step by step, and return descriptions of the changes
+pub fn migrate(db: &mut Value) -> Result<Vec<String>> {
+    let db = db
+        .as_object_mut()
+        .ok_or_else(|| anyhow!("database is not an object"))?;
+
+    let version = db
+        .get("schema_version")
+        .and_then(|x| x.as_u64())
+        .unwrap_or(0);
+
+    if version > SCHEMA_VERSION {
+        return Err(anyhow!(
+            "schema version {version} is newer than supported version {SCHEMA_VERSION}"
+        ));
+    }
+
+    let mut changes = vec![];
+    for (i, migration) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+        for change in migration(db) {
+            changes.push(format!("v{} -> v{}: {change}", i, i + 1));
+        }
+        db.insert("schema_version".to_string(), json!(i + 1));
+    }
+
+    Ok(changes)
+}
+
+fn projects(db: &mut Map<String, Value>) -> impl Iterator<Item = &mut Map<String, Value>> {
+    db.get_mut("projects")
+        .and_then(|x| x.as_object_mut())
+        .into_iter()
+        .flat_map(|x| x.values_mut())
+        .filter_map(|x| x.as_object_mut())
+}
+
+/// Add download statistics
+fn migrate_to_v1(db: &mut Map<String, Value>) -> Vec<String> {
+    let mut changes = vec![];
+    for key in ["veryl_downloads", "verylup_downloads"] {
+        if !db.contains_key(key) {
+            db.insert(key.to_string(), json!({}));
+            changes.push(format!("add {key}"));
+        }
+    }
+    changes
+}
+
+/// Replace bool build result with structured one
+fn migrate_to_v2(db: &mut Map<String, Value>) -> Vec<String> {
+    let mut count = 0;
+    for prj in projects(db) {
+        let Some(logs) = prj.get_mut("build_logs").and_then(|x| x.as_array_mut()) else {
+            continue;
+        };
+        for log in logs.iter_mut().filter_map(|x| x.as_object_mut()) {
+            let result = match log.get("result") {
+                Some(Value::Bool(true)) => json!("Success"),
+                Some(Value::Bool(false)) => json!({"CompileError": {"exit_code": null}}),
+                _ => continue,
+            };
+            log.insert("result".to_string(), result);
+            count += 1;
+        }
+    }
+
+    if count == 0 {
+        vec![]
+    } else {
+        vec![format!("convert {count} build results")]
+    }
+}
+
+/// Add search statistics and project ID allocator
+fn migrate_to_v3(db: &mut Map<String, Value>) -> Vec<String> {
+    let mut changes = vec![];
+
+    let mut count = 0;
+    if let Some(discovered) = db.get_mut("discovered").and_then(|x| x.as_array_mut()) {
+        for x in discovered.iter_mut().filter_map(|x| x.as_object_mut()) {
+            for key in ["pages", "items"] {
+                if !x.contains_key(key) {
+                    x.insert(key.to_string(), json!(0));
+                    count += 1;
+                }
+            }
+        }
+    }
+    if count != 0 {
+        changes.push(format!("add {count} search statistics to discovered"));
+    }
+
+    if !db.contains_key("next_project_id") {
+        let next = db
+            .get("projects")
+            .and_then(|x| x.as_object())
+            .and_then(|x| x.keys().filter_map(|x| x.parse::<u64>().ok()).max())
+            .map(|x| x + 1)
+            .unwrap_or(0);
+        db.insert("next_project_id".to_string(), json!(next));
+        changes.push(format!("set next_project_id to {next}"));
+    }
+
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{BuildResult, Db};
+
+    /// Database before schema versioning, in the shape of db/db.json
+    fn v0() -> Value {
+        json!({
+            "discovered": [
+                {"date": 1711687987, "sources": 100, "projects": [0, 2]},
+                {"date": 1711688139, "sources": 110, "projects": [0, 2, 7]}
+            ],
+            "projects": {
+                "0": {
+                    "url": "https://github.com/veryl-lang/sample",
+                    "build_logs": [
+                        {"rev": "7fb3d50", "veryl_version": "0.8.1", "result": true},
+                        {"rev": "e0a8a5a", "veryl_version": "0.9.0", "result": false}
+                    ]
+                },
+                "2": {
+                    "url": "https://github.com/veryl-lang/std",
+                    "build_logs": [
+                        {"rev": "b5303af", "veryl_version": "0.10.0", "result": true}
+                    ]
+                },
+                "7": {"url": "https://github.com/dalance/veryl-sample", "build_logs": []}
+            },
+            "veryl_downloads": {
+                "0.6.0": [
+                    {"date": 1727050738, "counts": {"X86_64Linux": 89, "Aarch64Mac": 22}}
+                ]
+            }
+        })
+    }
+
+    #[test]
+    fn migrate_v0() {
+        let mut value = v0();
+        let changes = migrate(&mut value).unwrap();
+        assert!(!changes.is_empty());
+
+        let db: Db = serde_json::from_value(value).unwrap();
+        assert_eq!(db.schema_version, SCHEMA_VERSION);
+        assert_eq!(db.next_project_id, 8);
+        assert_eq!(db.discovered.len(), 2);
+        assert_eq!(db.veryl_downloads.len(), 1);
+        assert!(db.verylup_downloads.is_empty());
+
+        let results: Vec<_> = db.projects[&0]
+            .build_logs
+            .iter()
+            .map(|x| x.result.clone())
+            .collect();
+        assert_eq!(
+            results,
+            [
+                BuildResult::Success,
+                BuildResult::CompileError { exit_code: None }
+            ]
+        );
+        assert_eq!(db.projects[&2].build_logs[0].result, BuildResult::Success);
+    }
+
+    #[test]
+    fn migrate_converts_all_results() {
+        let mut value = v0();
+        migrate(&mut value).unwrap();
+
+        let logs = value["projects"]
+            .as_object()
+            .unwrap()
+            .values()
+            .flat_map(|x| x["build_logs"].as_array().unwrap());
+        for log in logs {
+            assert!(!log["result"].is_boolean(), "{log}");
+        }
+    }
+
+    #[test]
+    fn migrate_is_idempotent() {
+        let mut value = v0();
+        migrate(&mut value).unwrap();
+        let migrated = value.clone();
+
+        let changes = migrate(&mut value).unwrap();
+        assert!(changes.is_empty());
+        assert_eq!(value, migrated);
+    }
+}
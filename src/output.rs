@@ -0,0 +1,232 @@
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use similar::TextDiff;
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::Path;
+use walkdir::WalkDir;
+
+/// Copy generated SystemVerilog files under the project to `dest` keeping relative paths
+pub fn collect(veryl_root: &Path, dest: &Path) -> Result<()> {
+    for entry in WalkDir::new(veryl_root)
+        .into_iter()
+        .filter_entry(|x| x.file_name() != ".git")
+    {
+        let entry = entry?;
+        if entry.file_type().is_file() && entry.path().extension().is_some_and(|x| x == "sv") {
+            let rel = entry.path().strip_prefix(veryl_root)?;
+            let dest = dest.join(rel);
+            fs::create_dir_all(dest.parent().unwrap())?;
+            fs::copy(entry.path(), dest)?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove comments and whitespace differences so that only meaningful changes are compared
+pub fn normalize(text: &str) -> String {
+    let mut code = String::new();
+    let mut chars = text.chars().peekable();
+    let mut in_string = false;
+    while let Some(c) = chars.next() {
+        if in_string {
+            code.push(c);
+            match c {
+                '\\' => code.extend(chars.next()),
+                '"' => in_string = false,
+                _ => (),
+            }
+            continue;
+        }
+        match (c, chars.peek()) {
+            ('"', _) => {
+                in_string = true;
+                code.push(c);
+            }
+            ('/', Some('/')) => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        code.push(c);
+                        break;
+                    }
+                }
+            }
+            ('/', Some('*')) => {
+                chars.next();
+                let mut prev = ' ';
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        code.push(c);
+                    }
+                    if prev == '*' && c == '/' {
+                        break;
+                    }
+                    prev = c;
+                }
+                code.push(' ');
+            }
+            _ => code.push(c),
+        }
+    }
+
+    let mut ret = String::new();
+    for line in code.lines() {
+        let line: Vec<_> = line.split_whitespace().collect();
+        if !line.is_empty() {
+            ret.push_str(&line.join(" "));
+            ret.push('\n');
+        }
+    }
+    ret
+}
+
+#[derive(Default)]
+pub struct OutputDiff {
+    /// Relative paths and unified diffs of changed files
+    pub changed: Vec<(String, String)>,
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+}
+
+impl OutputDiff {
+    pub fn is_empty(&self) -> bool {
+        self.changed.is_empty() && self.added.is_empty() && self.removed.is_empty()
+    }
+}
+
+/// Compare normalized outputs collected from two compiler runs
+pub fn diff(base: &Path, target: &Path) -> Result<OutputDiff> {
+    let base_files = files(base)?;
+    let target_files = files(target)?;
+
+    let mut ret = OutputDiff::default();
+    for file in base_files.union(&target_files) {
+        match (base_files.contains(file), target_files.contains(file)) {
+            (true, true) => {
+                let old = normalize(&fs::read_to_string(base.join(file))?);
+                let new = normalize(&fs::read_to_string(target.join(file))?);
+                if old != new {
+                    let diff = TextDiff::from_lines(&old, &new)
+                        .unified_diff()
+                        .header(&format!("a/{file}"), &format!("b/{file}"))
+                        .to_string();
+                    ret.changed.push((file.clone(), diff));
+                }
+            }
+            (true, false) => ret.removed.push(file.clone()),
+            _ => ret.added.push(file.clone()),
+        }
+    }
+    Ok(ret)
+}
+
+/// Hash of all files collected from a run, or `None` if nothing was collected
+pub fn hash(dir: &Path) -> Result<Option<String>> {
+    let files = files(dir)?;
+    if files.is_empty() {
+        return Ok(None);
+    }
+
+    let mut hasher = Sha256::new();
+    for file in files {
+        hasher.update(file.as_bytes());
+        hasher.update([0]);
+        hasher.update(fs::read(dir.join(&file))?);
+        hasher.update([0]);
+    }
+    let hash: String = hasher
+        .finalize()
+        .iter()
+        .map(|x| format!("{x:02x}"))
+        .collect();
+    Ok(Some(hash))
+}
+
+fn files(dir: &Path) -> Result<BTreeSet<String>> {
+    let mut ret = BTreeSet::new();
+    if !dir.exists() {
+        return Ok(ret);
+    }
+    for entry in WalkDir::new(dir) {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            let rel = entry.path().strip_prefix(dir)?;
+            let rel: Vec<_> = rel.iter().map(|x| x.to_string_lossy()).collect();
+            ret.insert(rel.join("/"));
+        }
+    }
+    Ok(ret)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalize_line_comment() {
+        assert_eq!(
+            normalize("assign a = b; // comment\n// whole line\nassign c = d;\n"),
+            "assign a = b;\nassign c = d;\n"
+        );
+    }
+
+    #[test]
+    fn normalize_block_comment() {
+        assert_eq!(
+            normalize("assign a = /* inline */ b;\n/*\n * multi\n * line\n */\nassign c = d;\n"),
+            "assign a = b;\nassign c = d;\n"
+        );
+    }
+
+    #[test]
+    fn normalize_comment_in_string() {
+        assert_eq!(
+            normalize("$display(\"// not a comment\"); // comment\n"),
+            "$display(\"// not a comment\");\n"
+        );
+        assert_eq!(
+            normalize("$display(\"/* not a comment */\");\n"),
+            "$display(\"/* not a comment */\");\n"
+        );
+    }
+
+    #[test]
+    fn normalize_escaped_quote() {
+        assert_eq!(
+            normalize("$display(\"say \\\"// hi\\\"\"); // comment\n"),
+            "$display(\"say \\\"// hi\\\"\");\n"
+        );
+    }
+
+    #[test]
+    fn normalize_whitespace() {
+        assert_eq!(
+            normalize("module  top;\n\n\t assign a =  b;  \r\nendmodule"),
+            "module top;\nassign a = b;\nendmodule\n"
+        );
+    }
+
+    #[test]
+    fn diff_ignores_comments_and_whitespace() {
+        let base = tempfile::tempdir().unwrap();
+        let target = tempfile::tempdir().unwrap();
+        fs::write(base.path().join("same.sv"), "module a;\nendmodule\n").unwrap();
+        fs::write(
+            target.path().join("same.sv"),
+            "// header\nmodule   a;\n\n  endmodule /* end */\n",
+        )
+        .unwrap();
+        fs::write(base.path().join("changed.sv"), "assign a = b;\n").unwrap();
+        fs::write(target.path().join("changed.sv"), "assign a = c;\n").unwrap();
+        fs::write(base.path().join("removed.sv"), "").unwrap();
+        fs::write(target.path().join("added.sv"), "").unwrap();
+
+        let diff = diff(base.path(), target.path()).unwrap();
+        let changed: Vec<_> = diff.changed.iter().map(|(x, _)| x.as_str()).collect();
+        assert_eq!(changed, ["changed.sv"]);
+        assert!(diff.changed[0].1.contains("-assign a = b;"));
+        assert!(diff.changed[0].1.contains("+assign a = c;"));
+        assert_eq!(diff.added, ["added.sv"]);
+        assert_eq!(diff.removed, ["removed.sv"]);
+    }
+}
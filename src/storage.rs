@@ -0,0 +1,551 @@
+use crate::db::{BuildLog, Db, Discovered, Download, Platform, Project};
+use crate::migration::{self, SCHEMA_VERSION};
+use anyhow::{anyhow, Result};
+use chrono::DateTime;
+use rusqlite::{params, Connection};
+use semver::Version;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use url::Url;
+
+const BACKUP_COUNT: usize = 3;
+
+pub trait Storage {
+    /// Load DB and upgrade it to the latest schema.
+    /// Descriptions of the applied migrations are returned with DB.
+    fn load(&self) -> Result<(Db, Vec<String>)>;
+
+    fn save(&self, db: &Db) -> Result<()>;
+}
+
+/// Select storage by the file extension
+pub fn open<T: AsRef<Path>>(path: T) -> Box<dyn Storage> {
+    let path = path.as_ref().to_path_buf();
+    match path.extension().and_then(|x| x.to_str()) {
+        Some("sqlite") | Some("sqlite3") | Some("db") => Box::new(SqliteStorage { path }),
+        _ => Box::new(JsonStorage { path }),
+    }
+}
+
+pub struct JsonStorage {
+    path: PathBuf,
+}
+
+impl Storage for JsonStorage {
+    fn load(&self) -> Result<(Db, Vec<String>)> {
+        let mut file = File::open(&self.path)?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        let text = String::from_utf8(buf)?;
+
+        #[derive(Deserialize)]
+        struct Header {
+            #[serde(default)]
+            schema_version: u64,
+        }
+
+        let header: Header = serde_json::from_str(&text)?;
+        if header.schema_version == SCHEMA_VERSION {
+            let db: Db = serde_json::from_str(&text)?;
+            return Ok((db, vec![]));
+        }
+
+        let mut value: serde_json::Value = serde_json::from_str(&text)?;
+        let changes = migration::migrate(&mut value)?;
+        let db: Db = serde_json::from_value(value)?;
+
+        Ok((db, changes))
+    }
+
+    fn save(&self, db: &Db) -> Result<()> {
+        let encoded: Vec<u8> = serde_json::to_string(db)?.into_bytes();
+        write_atomic(&self.path, &encoded)
+    }
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut ret = path.as_os_str().to_owned();
+    ret.push(suffix);
+    PathBuf::from(ret)
+}
+
+/// Write through a temporary file and rename it so that the file is never left truncated.
+/// The previous versions are kept as `<path>.bak.N`.
+fn write_atomic(path: &Path, data: &[u8]) -> Result<()> {
+    let tmp = with_suffix(path, ".tmp");
+
+    let mut file = File::create(&tmp)?;
+    file.write_all(data)?;
+    file.sync_all()?;
+    drop(file);
+
+    if path.exists() {
+        for i in (1..BACKUP_COUNT).rev() {
+            let src = with_suffix(path, &format!(".bak.{i}"));
+            if src.exists() {
+                fs::rename(&src, with_suffix(path, &format!(".bak.{}", i + 1)))?;
+            }
+        }
+        fs::copy(path, with_suffix(path, ".bak.1"))?;
+    }
+
+    fs::rename(&tmp, path)?;
+
+    // Persist the rename itself
+    let dir = path.parent().filter(|x| !x.as_os_str().is_empty());
+    File::open(dir.unwrap_or(Path::new(".")))?.sync_all()?;
+
+    Ok(())
+}
+
+/// Advisory lock to prevent concurrent runs from clobbering DB and build directory.
+/// The lock is released when this is dropped.
+pub struct Lock {
+    _file: File,
+}
+
+impl Lock {
+    pub fn acquire<T: AsRef<Path>>(path: T) -> Result<Lock> {
+        let path = path.as_ref();
+        let file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?;
+
+        // SAFETY: the file descriptor is valid while `file` is alive
+        let ret = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+        if ret != 0 {
+            return Err(anyhow!(
+                "{} is locked; another process may be running",
+                path.display()
+            ));
+        }
+
+        Ok(Lock { _file: file })
+    }
+}
+
+/// SQLite storage for ad-hoc queries over build history.
+/// Build logs are stored as JSON too, so new fields of BuildLog don't require table changes.
+pub struct SqliteStorage {
+    path: PathBuf,
+}
+
+const SQLITE_SCHEMA: &str = "
+CREATE TABLE meta (
+    key   TEXT PRIMARY KEY,
+    value TEXT NOT NULL
+);
+CREATE TABLE discovered (
+    id      INTEGER PRIMARY KEY,
+    date    INTEGER NOT NULL,
+    sources INTEGER NOT NULL,
+    pages   INTEGER NOT NULL,
+    items   INTEGER NOT NULL
+);
+CREATE TABLE discovered_projects (
+    discovered_id INTEGER NOT NULL REFERENCES discovered(id),
+    project_id    INTEGER NOT NULL
+);
+CREATE TABLE projects (
+    id   INTEGER PRIMARY KEY,
+    url  TEXT NOT NULL,
+    path TEXT NOT NULL
+);
+CREATE TABLE dependencies (
+    project_id INTEGER NOT NULL REFERENCES projects(id),
+    url        TEXT NOT NULL
+);
+CREATE TABLE build_logs (
+    project_id    INTEGER NOT NULL REFERENCES projects(id),
+    kind          TEXT NOT NULL,
+    seq           INTEGER NOT NULL,
+    rev           TEXT NOT NULL,
+    veryl_version TEXT NOT NULL,
+    result        TEXT NOT NULL,
+    log           TEXT NOT NULL,
+    PRIMARY KEY (project_id, kind, seq)
+);
+CREATE TABLE downloads (
+    kind     TEXT NOT NULL,
+    version  TEXT NOT NULL,
+    date     INTEGER NOT NULL,
+    platform TEXT NOT NULL,
+    count    INTEGER NOT NULL
+);
+";
+
+const BUILD_LOG_KIND: &str = "build";
+const BISECT_LOG_KIND: &str = "bisect";
+
+const SQLITE_TABLES: &[&str] = &[
+    "meta",
+    "discovered",
+    "discovered_projects",
+    "projects",
+    "dependencies",
+    "build_logs",
+    "downloads",
+];
+
+impl SqliteStorage {
+    fn meta(conn: &Connection, key: &str) -> Result<u64> {
+        let value: String =
+            conn.query_row("SELECT value FROM meta WHERE key = ?1", [key], |x| x.get(0))?;
+        Ok(value.parse()?)
+    }
+
+    fn load_downloads(conn: &Connection, kind: &str) -> Result<HashMap<Version, Vec<Download>>> {
+        let mut stmt = conn.prepare(
+            "SELECT version, date, platform, count FROM downloads WHERE kind = ?1 ORDER BY rowid",
+        )?;
+        let mut rows = stmt.query([kind])?;
+
+        let mut ret: HashMap<Version, Vec<Download>> = HashMap::new();
+        while let Some(row) = rows.next()? {
+            let version = Version::parse(&row.get::<_, String>(0)?)?;
+            let date = DateTime::from_timestamp(row.get(1)?, 0)
+                .ok_or_else(|| anyhow!("invalid timestamp"))?;
+            let platform: Platform =
+                serde_json::from_value(serde_json::Value::String(row.get(2)?))?;
+            let count: u64 = row.get(3)?;
+
+            let downloads = ret.entry(version).or_default();
+            match downloads.last_mut() {
+                Some(x) if x.date == date => {
+                    x.counts.insert(platform, count);
+                }
+                _ => {
+                    let counts = HashMap::from([(platform, count)]);
+                    downloads.push(Download { date, counts });
+                }
+            }
+        }
+
+        Ok(ret)
+    }
+
+    fn save_downloads(
+        conn: &Connection,
+        kind: &str,
+        downloads: &HashMap<Version, Vec<Download>>,
+    ) -> Result<()> {
+        let mut versions: Vec<_> = downloads.keys().collect();
+        versions.sort();
+
+        let mut stmt = conn.prepare(
+            "INSERT INTO downloads (kind, version, date, platform, count) VALUES (?1, ?2, ?3, ?4, ?5)",
+        )?;
+        for version in versions {
+            for download in &downloads[version] {
+                for (platform, count) in &download.counts {
+                    let platform = serde_json::to_value(platform)?;
+                    stmt.execute(params![
+                        kind,
+                        version.to_string(),
+                        download.date.timestamp(),
+                        platform.as_str().unwrap(),
+                        count,
+                    ])?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Storage for SqliteStorage {
+    fn load(&self) -> Result<(Db, Vec<String>)> {
+        if !self.path.exists() {
+            return Err(anyhow!("{} is not found", self.path.display()));
+        }
+        let conn = Connection::open(&self.path)?;
+
+        let schema_version = Self::meta(&conn, "schema_version")?;
+        if schema_version != SCHEMA_VERSION {
+            return Err(anyhow!(
+                "schema version {schema_version} is not supported; export it again from db.json"
+            ));
+        }
+
+        let mut db = Db::new();
+        db.next_project_id = Self::meta(&conn, "next_project_id")?;
+
+        let mut stmt = conn.prepare("SELECT id, url, path FROM projects")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let id: u64 = row.get(0)?;
+            let url = Url::parse(&row.get::<_, String>(1)?)?;
+            let path: String = row.get(2)?;
+            let prj = Project {
+                url,
+                path,
+                build_logs: vec![],
+                dependencies: vec![],
+                bisect_logs: vec![],
+            };
+            db.projects.insert(id, prj);
+        }
+
+        let mut stmt = conn.prepare("SELECT project_id, url FROM dependencies ORDER BY rowid")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let id: u64 = row.get(0)?;
+            let url = Url::parse(&row.get::<_, String>(1)?)?;
+            let prj = db
+                .projects
+                .get_mut(&id)
+                .ok_or_else(|| anyhow!("dependency refers to unknown project {id}"))?;
+            prj.dependencies.push(url);
+        }
+
+        let mut stmt = conn.prepare("SELECT project_id, kind, log FROM build_logs ORDER BY seq")?;
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let id: u64 = row.get(0)?;
+            let kind: String = row.get(1)?;
+            let log: BuildLog = serde_json::from_str(&row.get::<_, String>(2)?)?;
+            let prj = db
+                .projects
+                .get_mut(&id)
+                .ok_or_else(|| anyhow!("build log refers to unknown project {id}"))?;
+            match kind.as_str() {
+                BUILD_LOG_KIND => prj.build_logs.push(log),
+                BISECT_LOG_KIND => prj.bisect_logs.push(log),
+                _ => return Err(anyhow!("unknown build log kind \"{kind}\"")),
+            }
+        }
+
+        let mut stmt =
+            conn.prepare("SELECT id, date, sources, pages, items FROM discovered ORDER BY id")?;
+        let mut ids = vec![];
+        let mut rows = stmt.query([])?;
+        while let Some(row) = rows.next()? {
+            let id: u64 = row.get(0)?;
+            let date = DateTime::from_timestamp(row.get(1)?, 0)
+                .ok_or_else(|| anyhow!("invalid timestamp"))?;
+            db.discovered.push(Discovered {
+                date,
+                sources: row.get(2)?,
+                projects: vec![],
+                pages: row.get(3)?,
+                items: row.get(4)?,
+            });
+            ids.push(id);
+        }
+
+        let mut stmt = conn.prepare(
+            "SELECT project_id FROM discovered_projects WHERE discovered_id = ?1 ORDER BY rowid",
+        )?;
+        for (id, discovered) in ids.iter().zip(db.discovered.iter_mut()) {
+            let rows = stmt.query_map([id], |x| x.get::<_, u64>(0))?;
+            for project_id in rows {
+                discovered.projects.push(project_id?);
+            }
+        }
+
+        db.veryl_downloads = Self::load_downloads(&conn, "veryl")?;
+        db.verylup_downloads = Self::load_downloads(&conn, "verylup")?;
+
+        Ok((db, vec![]))
+    }
+
+    fn save(&self, db: &Db) -> Result<()> {
+        let mut conn = Connection::open(&self.path)?;
+        let tx = conn.transaction()?;
+
+        // Tables are recreated so that older files follow the latest table definitions
+        for table in SQLITE_TABLES.iter().rev() {
+            tx.execute(&format!("DROP TABLE IF EXISTS {table}"), [])?;
+        }
+        tx.execute_batch(SQLITE_SCHEMA)?;
+
+        tx.execute(
+            "INSERT INTO meta (key, value) VALUES ('schema_version', ?1), ('next_project_id', ?2)",
+            params![
+                db.schema_version.to_string(),
+                db.next_project_id.to_string()
+            ],
+        )?;
+
+        for (id, discovered) in db.discovered.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO discovered (id, date, sources, pages, items) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    id,
+                    discovered.date.timestamp(),
+                    discovered.sources,
+                    discovered.pages,
+                    discovered.items,
+                ],
+            )?;
+            for project_id in &discovered.projects {
+                tx.execute(
+                    "INSERT INTO discovered_projects (discovered_id, project_id) VALUES (?1, ?2)",
+                    params![id, project_id],
+                )?;
+            }
+        }
+
+        for (id, prj) in &db.projects {
+            tx.execute(
+                "INSERT INTO projects (id, url, path) VALUES (?1, ?2, ?3)",
+                params![id, prj.url.as_str(), prj.path],
+            )?;
+            for url in &prj.dependencies {
+                tx.execute(
+                    "INSERT INTO dependencies (project_id, url) VALUES (?1, ?2)",
+                    params![id, url.as_str()],
+                )?;
+            }
+            let build_logs = prj.build_logs.iter().map(|x| (BUILD_LOG_KIND, x));
+            let bisect_logs = prj.bisect_logs.iter().map(|x| (BISECT_LOG_KIND, x));
+            let logs = build_logs.enumerate().chain(bisect_logs.enumerate());
+            for (seq, (kind, log)) in logs {
+                tx.execute(
+                    "INSERT INTO build_logs (project_id, kind, seq, rev, veryl_version, result, log) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+                    params![
+                        id,
+                        kind,
+                        seq,
+                        log.rev,
+                        log.veryl_version.to_string(),
+                        log.result.to_string(),
+                        serde_json::to_string(log)?,
+                    ],
+                )?;
+            }
+        }
+
+        Self::save_downloads(&tx, "veryl", &db.veryl_downloads)?;
+        Self::save_downloads(&tx, "verylup", &db.verylup_downloads)?;
+
+        tx.commit()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{BuildResult, Step, StepLog};
+    use crate::lint::{LintLog, LintResult, LintTool};
+    use crate::usage::ResourceUsage;
+    use chrono::{TimeZone, Utc};
+
+    fn build_log(rev: &str, result: BuildResult) -> BuildLog {
+        BuildLog {
+            rev: rev.to_string(),
+            veryl_version: Version::new(0, 13, 0),
+            result,
+            stdout: String::new(),
+            stderr: String::new(),
+            usage: None,
+            steps: vec![],
+            lint: None,
+        }
+    }
+
+    fn sample() -> Db {
+        let mut db = Db::new();
+        let date = Utc.timestamp_opt(1711687987, 0).unwrap();
+
+        let mut failed = build_log("b", BuildResult::CompileError { exit_code: Some(1) });
+        failed.stderr = "error: unknown identifier".to_string();
+        let mut checked = build_log("c", BuildResult::Success);
+        checked.usage = Some(ResourceUsage {
+            wall_time: 1.5,
+            user_time: 1.0,
+            sys_time: 0.25,
+            max_rss: 1 << 20,
+        });
+        checked.steps = vec![StepLog {
+            step: Step::Fmt,
+            result: BuildResult::Success,
+            stdout: String::new(),
+            stderr: String::new(),
+            usage: None,
+        }];
+        checked.lint = Some(LintLog {
+            tool: LintTool::Verilator,
+            result: LintResult::Error { exit_code: Some(1) },
+            stderr: "%Error: bad".to_string(),
+        });
+
+        let root = db.insert_project(Project {
+            url: Url::parse("https://github.com/veryl-lang/sample").unwrap(),
+            path: String::new(),
+            build_logs: vec![build_log("a", BuildResult::Success), failed],
+            dependencies: vec![],
+            bisect_logs: vec![],
+        });
+        let sub = db.insert_project(Project {
+            url: Url::parse("https://github.com/veryl-lang/mono").unwrap(),
+            path: "hw/core".to_string(),
+            build_logs: vec![checked],
+            dependencies: vec![
+                Url::parse("https://github.com/veryl-lang/sample").unwrap(),
+                Url::parse("https://gitlab.com/veryl-lang/std").unwrap(),
+            ],
+            bisect_logs: vec![build_log("d", BuildResult::NoVerylToml)],
+        });
+
+        db.discovered.push(Discovered {
+            date,
+            sources: 100,
+            projects: vec![root, sub],
+            pages: 2,
+            items: 150,
+        });
+        db.veryl_downloads.insert(
+            Version::new(0, 13, 0),
+            vec![Download {
+                date,
+                counts: HashMap::from([(Platform::X86_64Linux, 89), (Platform::Aarch64Mac, 22)]),
+            }],
+        );
+        db.verylup_downloads.insert(
+            Version::new(0, 1, 0),
+            vec![Download {
+                date,
+                counts: HashMap::from([(Platform::X86_64Windows, 3)]),
+            }],
+        );
+        db
+    }
+
+    fn value(db: &Db) -> serde_json::Value {
+        serde_json::to_value(db).unwrap()
+    }
+
+    #[test]
+    fn json_sqlite_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = sample();
+
+        let json = dir.path().join("db.json");
+        open(&json).save(&db).unwrap();
+        let (from_json, migrations) = open(&json).load().unwrap();
+        assert!(migrations.is_empty());
+        assert_eq!(value(&from_json), value(&db));
+
+        let sqlite = dir.path().join("db.sqlite");
+        open(&sqlite).save(&from_json).unwrap();
+        let (from_sqlite, migrations) = open(&sqlite).load().unwrap();
+        assert!(migrations.is_empty());
+        assert_eq!(value(&from_sqlite), value(&db));
+
+        // Import back into JSON
+        let json = dir.path().join("imported.json");
+        open(&json).save(&from_sqlite).unwrap();
+        let (imported, _) = open(&json).load().unwrap();
+        assert_eq!(value(&imported), value(&db));
+    }
+}
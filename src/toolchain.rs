@@ -0,0 +1,161 @@
+use crate::db::{GithubRelease, VERYL_RELEASE_API};
+use anyhow::{anyhow, Result};
+use semver::Version;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+
+const TOOLCHAIN_DIR: &str = "toolchains";
+/// File which records SHA-256 of the verified archive next to the extracted binary
+const HASH_FILE: &str = "archive.sha256";
+
+/// Veryl release to be downloaded
+pub struct Release {
+    /// The latest release if not specified
+    pub version: Option<Version>,
+    /// Pinned SHA-256 of the archive which takes precedence over the published one
+    pub sha256: Option<String>,
+}
+
+/// Suffix of the release asset for the host
+fn host_platform() -> Result<&'static str> {
+    match (std::env::consts::ARCH, std::env::consts::OS) {
+        ("x86_64", "linux") => Ok("x86_64-linux"),
+        ("x86_64", "macos") => Ok("x86_64-mac"),
+        ("aarch64", "macos") => Ok("aarch64-mac"),
+        ("x86_64", "windows") => Ok("x86_64-windows"),
+        (arch, os) => Err(anyhow!("no Veryl release for {arch}-{os}")),
+    }
+}
+
+fn sha256(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .map(|x| format!("{x:02x}"))
+        .collect()
+}
+
+/// Return veryl of the installed release if its archive hash is recorded and matches the pinned one
+fn cached(dir: &Path, version: &str, release: &Release) -> Result<Option<PathBuf>> {
+    let toolchain = dir.join(TOOLCHAIN_DIR).join(version);
+    let veryl = toolchain.join("veryl");
+    let Ok(actual) = fs::read_to_string(toolchain.join(HASH_FILE)) else {
+        return Ok(None);
+    };
+    if !veryl.exists() {
+        return Ok(None);
+    }
+
+    let actual = actual.trim();
+    if let Some(expected) = &release.sha256 {
+        if actual != expected.to_lowercase() {
+            return Err(anyhow!(
+                "checksum mismatch of installed Veryl {version}: expected {expected}, actual {actual}"
+            ));
+        }
+    }
+    Ok(Some(veryl.canonicalize()?))
+}
+
+/// Download the release for the host into `toolchains/<version>`, and return the path of veryl.
+/// Downloaded releases are reused, and the archive is verified before extraction.
+pub async fn install(dir: &Path, release: &Release) -> Result<PathBuf> {
+    if let Some(x) = &release.version {
+        if let Some(veryl) = cached(dir, &x.to_string(), release)? {
+            return Ok(veryl);
+        }
+    }
+
+    let client = reqwest::Client::builder()
+        .user_agent("veryl-discovery/0.1.0")
+        .build()?;
+
+    let api = if let Some(x) = &release.version {
+        format!("{VERYL_RELEASE_API}/tags/v{x}")
+    } else {
+        format!("{VERYL_RELEASE_API}/latest")
+    };
+    let github_release = client
+        .get(api)
+        .send()
+        .await?
+        .error_for_status()?
+        .json::<GithubRelease>()
+        .await?;
+    let version = github_release.name.trim_start_matches('v');
+
+    if let Some(veryl) = cached(dir, version, release)? {
+        return Ok(veryl);
+    }
+
+    let name = format!("veryl-{}.zip", host_platform()?);
+    let asset = github_release
+        .assets
+        .iter()
+        .find(|x| x.name == name)
+        .ok_or_else(|| anyhow!("{name} is not found in Veryl {version}"))?;
+
+    // Pinned hash, digest provided by GitHub, and checksum file in the release are tried in order
+    let expected = if let Some(x) = &release.sha256 {
+        x.to_lowercase()
+    } else if let Some(x) = asset
+        .digest
+        .as_ref()
+        .and_then(|x| x.strip_prefix("sha256:"))
+    {
+        x.to_string()
+    } else if let Some(x) = github_release
+        .assets
+        .iter()
+        .find(|x| x.name == format!("{name}.sha256"))
+    {
+        let text = client
+            .get(&x.browser_download_url)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
+        text.split_whitespace()
+            .next()
+            .unwrap_or_default()
+            .to_lowercase()
+    } else {
+        return Err(anyhow!(
+            "no checksum of {name} is published in Veryl {version}, specify it by --sha256"
+        ));
+    };
+
+    println!("Downloading {name} of Veryl {version}");
+    let archive = client
+        .get(&asset.browser_download_url)
+        .send()
+        .await?
+        .error_for_status()?
+        .bytes()
+        .await?;
+    let actual = sha256(&archive);
+    if actual != expected {
+        return Err(anyhow!(
+            "checksum mismatch of {name}: expected {expected}, actual {actual}"
+        ));
+    }
+
+    // Extract into a temporary directory so that an interrupted extraction is not reused
+    let tmp = dir.join(TOOLCHAIN_DIR).join(format!("{version}.tmp"));
+    if tmp.exists() {
+        fs::remove_dir_all(&tmp)?;
+    }
+    zip_extract::extract(Cursor::new(archive), &tmp, true)?;
+    fs::write(tmp.join(HASH_FILE), &actual)?;
+
+    // Installation without the recorded hash is replaced
+    let toolchain = dir.join(TOOLCHAIN_DIR).join(version);
+    if toolchain.exists() {
+        fs::remove_dir_all(&toolchain)?;
+    }
+    fs::rename(&tmp, &toolchain)?;
+
+    Ok(toolchain.join("veryl").canonicalize()?)
+}
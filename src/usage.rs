@@ -0,0 +1,135 @@
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Command, ExitStatus, Output, Stdio};
+use std::time::{Duration, Instant};
+use tokio::io::AsyncReadExt;
+use tokio::process::{ChildStderr, ChildStdout};
+use tokio::time;
+
+/// Resources used by a process and its waited-for descendants
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct ResourceUsage {
+    /// Elapsed real time in seconds
+    pub wall_time: f64,
+    /// User CPU time in seconds
+    pub user_time: f64,
+    /// System CPU time in seconds
+    pub sys_time: f64,
+    /// Peak resident set size in bytes
+    pub max_rss: u64,
+}
+
+impl ResourceUsage {
+    pub fn cpu_time(&self) -> f64 {
+        self.user_time + self.sys_time
+    }
+}
+
+fn seconds(x: libc::timeval) -> f64 {
+    x.tv_sec as f64 + x.tv_usec as f64 / 1_000_000.0
+}
+
+/// Wait for the process, and return the raw exit status and resource usage
+fn wait4(pid: libc::pid_t) -> io::Result<(i32, libc::rusage)> {
+    let mut status = 0;
+    let mut rusage = MaybeUninit::<libc::rusage>::zeroed();
+    // SAFETY: rusage is a plain C struct filled by wait4
+    unsafe {
+        if libc::wait4(pid, &mut status, 0, rusage.as_mut_ptr()) < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok((status, rusage.assume_init()))
+    }
+}
+
+/// Wait for the process to exit without reaping it, so that the pid is not reused until wait4
+fn wait_exit(pid: libc::pid_t) -> io::Result<()> {
+    let mut info = MaybeUninit::<libc::siginfo_t>::zeroed();
+    loop {
+        // SAFETY: info is a plain C struct filled by waitid
+        let ret = unsafe {
+            libc::waitid(
+                libc::P_PID,
+                pid as libc::id_t,
+                info.as_mut_ptr(),
+                libc::WEXITED | libc::WNOWAIT,
+            )
+        };
+        if ret == 0 {
+            return Ok(());
+        }
+        let err = io::Error::last_os_error();
+        if err.kind() != io::ErrorKind::Interrupted {
+            return Err(err);
+        }
+    }
+}
+
+/// Run the command and collect its output and resource usage.
+/// `tokio::process` reaps children by itself, so the process is spawned by `std::process` and reaped by wait4.
+/// The process is killed and `None` is returned on timeout.
+pub async fn output(
+    mut cmd: Command,
+    timeout: Duration,
+) -> io::Result<Option<(Output, ResourceUsage)>> {
+    cmd.stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped());
+
+    let start = Instant::now();
+    let mut child = cmd.spawn()?;
+    let pid = child.id() as libc::pid_t;
+    let mut stdout = ChildStdout::from_std(child.stdout.take().unwrap())?;
+    let mut stderr = ChildStderr::from_std(child.stderr.take().unwrap())?;
+
+    let mut exited = tokio::task::spawn_blocking(move || wait_exit(pid));
+    let mut is_exited = false;
+
+    let run = async {
+        let mut stdout_buf = vec![];
+        let mut stderr_buf = vec![];
+        tokio::try_join!(
+            stdout.read_to_end(&mut stdout_buf),
+            stderr.read_to_end(&mut stderr_buf),
+            async {
+                (&mut exited).await.map_err(io::Error::other)??;
+                is_exited = true;
+                io::Result::Ok(())
+            }
+        )?;
+        // The process has already exited, so this doesn't block
+        let (status, rusage) = wait4(pid)?;
+
+        let usage = ResourceUsage {
+            wall_time: start.elapsed().as_secs_f64(),
+            user_time: seconds(rusage.ru_utime),
+            sys_time: seconds(rusage.ru_stime),
+            // ru_maxrss is in KiB on Linux
+            max_rss: rusage.ru_maxrss as u64 * 1024,
+        };
+        let output = Output {
+            status: ExitStatus::from_raw(status),
+            stdout: stdout_buf,
+            stderr: stderr_buf,
+        };
+        io::Result::Ok((output, usage))
+    };
+
+    match time::timeout(timeout, run).await {
+        Ok(x) => Ok(Some(x?)),
+        Err(_) => {
+            // SAFETY: the process is reaped only by wait4 below, so the pid is not reused
+            unsafe {
+                libc::kill(pid, libc::SIGKILL);
+            }
+            // The wait task may have finished while the pipes are held by descendants
+            if !is_exited {
+                exited.await.map_err(io::Error::other)??;
+            }
+            wait4(pid)?;
+            Ok(None)
+        }
+    }
+}